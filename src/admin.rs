@@ -0,0 +1,214 @@
+use super::cli::{UserAction, UsersArgs};
+use super::config::Config;
+use super::credentials::{CredentialRecord, CredentialsManager};
+use super::crypto::{reset_token, PasswordHash};
+use super::error::{Error, Result};
+use super::login::Login;
+
+/// Management of user accounts, which is available to administrators only
+pub(super) struct Admin;
+
+impl Admin {
+    const ADMIN_LEVEL: &'static str = "admin";
+    const USERS_MESSAGE: &'static str = "Пользователи:";
+    const UNKNOWN_LOGIN: &'static str = "<логин неизвестен до первого входа>";
+    const MUST_CHANGE_PASSWORD: &'static str = "требуется смена пароля";
+    const DELETED_MESSAGE: &'static str = "Пользователь удалён";
+    const ACCESS_LEVEL_MESSAGE: &'static str = "Уровень доступа изменён";
+    const RESET_PASSWORD_MESSAGE: &'static str =
+        "Одноразовый код для входа пользователя, после которого он сменит пароль:";
+
+    /// Authenticates the administrator and performs the action.
+    /// Values of the administrator, missing in `args`, are asked interactively
+    pub(super) fn run(config: &Config, args: &UsersArgs) -> Result<()> {
+        let (_, admin) = Login::sign_in(config, &args.admin)?;
+        if admin.access_level != Self::ADMIN_LEVEL {
+            return Err(Error::Policy(
+                "Only administrators may manage users".to_string(),
+            ));
+        }
+        if admin.must_change_password {
+            return Err(Error::Policy(
+                "Password must be changed on login before managing users".to_string(),
+            ));
+        }
+
+        let mut credentials = CredentialsManager::open(config)?;
+        if args.action == UserAction::List {
+            println!("{}", Self::USERS_MESSAGE);
+            for line in Self::list(&credentials) {
+                println!("  {}", line);
+            }
+            return Ok(());
+        }
+        let message = Self::change(&mut credentials, &args.action, config.hash_iterations)?;
+        credentials.save()?;
+        println!("{}", message);
+        Ok(())
+    }
+
+    /// Line for each user, sorted by login. Users, whose login is unknown, go last
+    fn list(credentials: &CredentialsManager) -> Vec<String> {
+        let mut records: Vec<&CredentialRecord> = credentials.records().collect();
+        records.sort_by_key(|record| (record.login.is_none(), record.login.clone()));
+        records
+            .into_iter()
+            .map(|record| {
+                let mut line = format!(
+                    "{}\t{}",
+                    record.login.as_deref().unwrap_or(Self::UNKNOWN_LOGIN),
+                    record.access_level
+                );
+                if record.must_change_password {
+                    line = format!("{}\t({})", line, Self::MUST_CHANGE_PASSWORD);
+                }
+                line
+            })
+            .collect()
+    }
+
+    /// Applies the action, which changes the store. Returns message for the administrator.
+    /// Reset tokens are hashed with `iterations` work factor
+    fn change(
+        credentials: &mut CredentialsManager,
+        action: &UserAction,
+        iterations: u32,
+    ) -> Result<String> {
+        match action {
+            UserAction::List => Ok(Self::USERS_MESSAGE.to_string()),
+            UserAction::Delete(login) => {
+                Self::check_admin_remains(credentials, login)?;
+                credentials.delete(login);
+                Ok(Self::DELETED_MESSAGE.to_string())
+            }
+            UserAction::SetAccessLevel(login, access_level) => {
+                if access_level != Self::ADMIN_LEVEL {
+                    Self::check_admin_remains(credentials, login)?;
+                }
+                let mut record = Self::find(credentials, login)?.clone();
+                record.access_level = access_level.clone();
+                credentials.update(login, record);
+                Ok(Self::ACCESS_LEVEL_MESSAGE.to_string())
+            }
+            UserAction::ResetPassword(login) => {
+                let mut record = Self::find(credentials, login)?.clone();
+                let token = reset_token()?;
+                record.must_change_password = true;
+                record.reset_token = Some(PasswordHash::new(&token, iterations)?);
+                credentials.update(login, record);
+                Ok(format!("{}\n{}", Self::RESET_PASSWORD_MESSAGE, token))
+            }
+        }
+    }
+
+    fn find<'a>(credentials: &'a CredentialsManager, login: &str) -> Result<&'a CredentialRecord> {
+        credentials
+            .get(login)
+            .ok_or_else(|| Error::Validation(format!("There is no user {}", login)))
+    }
+
+    /// Fails, if the user exists and is the only administrator, so the store
+    /// would be left without anyone to manage it
+    fn check_admin_remains(credentials: &CredentialsManager, login: &str) -> Result<()> {
+        if Self::find(credentials, login)?.access_level != Self::ADMIN_LEVEL {
+            return Ok(());
+        }
+        let admins = credentials
+            .records()
+            .filter(|record| record.access_level == Self::ADMIN_LEVEL)
+            .count();
+        if admins == 1 {
+            return Err(Error::Policy(
+                "The last administrator can not be removed or demoted".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Admin, CredentialRecord, CredentialsManager, PasswordHash, UserAction};
+
+    fn credentials() -> CredentialsManager {
+        let mut credentials = CredentialsManager::in_memory();
+        for (login, level) in [("root", "admin"), ("bob", "user"), ("alice", "user")] {
+            let password = PasswordHash::new("somePa@_ss1", 10).unwrap();
+            credentials.insert(login, CredentialRecord::new(password, level.to_string()));
+        }
+        credentials
+    }
+
+    fn change(credentials: &mut CredentialsManager, action: UserAction) -> bool {
+        Admin::change(credentials, &action, 10).is_ok()
+    }
+
+    #[test]
+    fn list_users() {
+        let mut credentials = credentials();
+        change(
+            &mut credentials,
+            UserAction::ResetPassword("bob".to_string()),
+        );
+        assert_eq!(
+            Admin::list(&credentials),
+            vec![
+                "alice\tuser",
+                "bob\tuser\t(требуется смена пароля)",
+                "root\tadmin"
+            ]
+        );
+    }
+
+    #[test]
+    fn change_users() {
+        use UserAction::*;
+
+        let mut credentials = credentials();
+        let message =
+            Admin::change(&mut credentials, &ResetPassword("alice".to_string()), 10).unwrap();
+        let token = message.lines().last().unwrap();
+        let alice = credentials.get("alice").unwrap();
+        assert!(alice.must_change_password);
+        assert!(alice.reset_token.as_ref().unwrap().verify(token));
+        assert!(change(
+            &mut credentials,
+            SetAccessLevel("alice".to_string(), "admin".to_string())
+        ));
+        let alice = credentials.get("alice").unwrap();
+        assert_eq!(alice.access_level, "admin");
+        assert!(alice.must_change_password);
+
+        assert!(change(&mut credentials, Delete("bob".to_string())));
+        assert!(credentials.get("bob").is_none());
+        for action in [
+            Delete("bob".to_string()),
+            ResetPassword("bob".to_string()),
+            SetAccessLevel("bob".to_string(), "user".to_string()),
+        ] {
+            assert!(!change(&mut credentials, action));
+        }
+    }
+
+    #[test]
+    fn keep_last_admin() {
+        use UserAction::*;
+
+        let mut credentials = credentials();
+        assert!(!change(&mut credentials, Delete("root".to_string())));
+        assert!(!change(
+            &mut credentials,
+            SetAccessLevel("root".to_string(), "user".to_string())
+        ));
+        assert!(change(
+            &mut credentials,
+            SetAccessLevel("root".to_string(), "admin".to_string())
+        ));
+        assert!(change(
+            &mut credentials,
+            SetAccessLevel("bob".to_string(), "admin".to_string())
+        ));
+        assert!(change(&mut credentials, Delete("root".to_string())));
+        assert_eq!(credentials.get("bob").unwrap().access_level, "admin");
+    }
+}
@@ -0,0 +1,495 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use super::admin::Admin;
+use super::config::{Config, Settings};
+use super::error::{Error, Result};
+use super::login::Login;
+use super::registrar::Registrar;
+use super::utils::{read_secret, read_secret_file, read_stdin};
+
+/// Where a password is taken from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) enum Secret {
+    /// Asked on the terminal without echo, or read from stdin, if it is not a terminal
+    #[default]
+    Prompt,
+    /// First line of a file, e.g. `/dev/fd/3`
+    File(PathBuf),
+}
+
+impl Secret {
+    /// Reads the secret, showing the prompt if it is asked for
+    pub(super) fn read(&self, prompt_msg: &str, app_err: &str) -> Result<String> {
+        match self {
+            Secret::Prompt => {
+                println!("{}\t", prompt_msg);
+                read_secret().map_err(|native_err| Error::io(app_err, native_err))
+            }
+            Secret::File(path) => read_secret_file(path).map_err(|native_err| {
+                Error::io(format!("{} from {}", app_err, path.display()), native_err)
+            }),
+        }
+    }
+}
+
+/// Values of `register` command. Missing ones are asked interactively
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct RegisterArgs {
+    pub(super) login: Option<String>,
+    pub(super) access_level: Option<String>,
+    pub(super) password: Secret,
+}
+
+/// Values of `login` command. Missing ones are asked interactively
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct LoginArgs {
+    pub(super) login: Option<String>,
+    pub(super) password: Secret,
+}
+
+/// Values of `passwd` command. Missing ones are asked interactively
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct PasswdArgs {
+    /// The user and the current password or reset token
+    pub(super) user: LoginArgs,
+    pub(super) new_password: Secret,
+}
+
+/// Change of user accounts, made by an administrator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum UserAction {
+    List,
+    /// Removes the user with the login
+    Delete(String),
+    /// Sets access level of the user with the login
+    SetAccessLevel(String, String),
+    /// Issues one-time reset token to the user with the login, who has to change
+    /// the password on the next login
+    ResetPassword(String),
+}
+
+/// Values of `users` command. The administrator is authenticated with `admin`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct UsersArgs {
+    pub(super) admin: LoginArgs,
+    pub(super) action: UserAction,
+}
+
+/// Parsed command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Cli {
+    /// Command to run, the interactive menu is shown if there is none
+    pub(super) command: Option<Command>,
+    /// Config file, given by `--config`
+    pub(super) config_path: Option<PathBuf>,
+    /// Settings, given by flags. They override the environment and the config file
+    pub(super) settings: Settings,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Command {
+    /// Registers the first administrator in an empty store
+    Init(RegisterArgs),
+    Register(RegisterArgs),
+    Login(LoginArgs),
+    /// Changes password of the user
+    Passwd(PasswdArgs),
+    /// Manages user accounts on behalf of an administrator
+    Users(UsersArgs),
+    Help,
+}
+
+impl Command {
+    const MENU_MESSAGE: &'static str = "Введите номер действия:
+            1 Регистрация
+            2 Вход
+            3 Смена пароля";
+    const USAGE: &'static str = "Использование:
+    md5 [НАСТРОЙКИ]                         интерактивное меню
+    md5 init [--login ЛОГИН] [ПАРОЛЬ] [НАСТРОЙКИ]       первый администратор
+    md5 register [--login ЛОГИН] [--access-level user] [ПАРОЛЬ] [НАСТРОЙКИ]
+    md5 login [--login ЛОГИН] [ПАРОЛЬ] [НАСТРОЙКИ]
+    md5 passwd [--login ЛОГИН] [ПАРОЛЬ] [НОВЫЙ ПАРОЛЬ] [НАСТРОЙКИ]
+    md5 users ДЕЙСТВИЕ [--login ЛОГИН] [ПАРОЛЬ] [НАСТРОЙКИ]   от имени администратора
+    md5 help
+
+ДЕЙСТВИЕ:
+    list                                список пользователей
+    delete ПОЛЬЗОВАТЕЛЬ                 удалить пользователя
+    set-level ПОЛЬЗОВАТЕЛЬ admin|user   изменить уровень доступа
+    reset-password ПОЛЬЗОВАТЕЛЬ         выдать одноразовый код для входа со сменой пароля
+
+ПАРОЛЬ:
+    --password-file ФАЙЛ    прочитать пароль из первой строки файла
+    --password-fd N         прочитать пароль из дескриптора N
+По умолчанию пароль читается со стандартного ввода.
+После сброса пароля администратором вместо него вводится выданный одноразовый код.
+
+НОВЫЙ ПАРОЛЬ:
+    --new-password-file ФАЙЛ
+    --new-password-fd N
+
+НАСТРОЙКИ:
+    --config ФАЙЛ           файл конфигурации
+    --storage json|log|memory
+    --credentials ФАЙЛ      хранилище учётных данных
+    --password-policy ФАЙЛ  политика паролей
+    --document-dir КАТАЛОГ  каталог документов
+    --max-attempts N        неудачных попыток входа до блокировки
+    --lockout-window СЕК    длительность первой блокировки
+    --backoff-factor N      во сколько раз растёт каждая следующая блокировка
+    --max-lockout СЕК       наибольшая длительность блокировки";
+
+    /// Parses command line arguments without the program name
+    pub(super) fn parse<I>(args: I) -> Result<Cli>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut positional = Vec::new();
+        let mut login = None;
+        let mut access_level = None;
+        let mut password = Secret::Prompt;
+        let mut new_password = Secret::Prompt;
+        let mut config_path = None;
+        let mut settings = Settings::default();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| Error::Validation(format!("Missing value of {}", arg)))
+            };
+            match arg.as_str() {
+                "--login" => login = Some(value()?),
+                "--access-level" => access_level = Some(Self::parse_access_level(&value()?)?),
+                "--password-file" => password = Secret::File(PathBuf::from(value()?)),
+                "--password-fd" => password = Self::parse_fd(&arg, &value()?)?,
+                "--new-password-file" => new_password = Secret::File(PathBuf::from(value()?)),
+                "--new-password-fd" => new_password = Self::parse_fd(&arg, &value()?)?,
+                "--config" => config_path = Some(PathBuf::from(value()?)),
+                "--storage" => {
+                    settings.storage = Some(value()?.parse().map_err(Error::Validation)?)
+                }
+                "--credentials" => settings.credentials_path = Some(PathBuf::from(value()?)),
+                "--password-policy" => settings.password_policy = Some(PathBuf::from(value()?)),
+                "--document-dir" => settings.document_dir = Some(PathBuf::from(value()?)),
+                "--max-attempts" => {
+                    settings.max_attempts = Some(Self::parse_number(&arg, &value()?)?)
+                }
+                "--lockout-window" => {
+                    settings.lockout_window_secs = Some(Self::parse_number(&arg, &value()?)?)
+                }
+                "--backoff-factor" => {
+                    settings.backoff_factor = Some(Self::parse_number(&arg, &value()?)?)
+                }
+                "--max-lockout" => {
+                    settings.max_lockout_secs = Some(Self::parse_number(&arg, &value()?)?)
+                }
+                "--help" | "-h" if positional.is_empty() => positional.push("help".to_string()),
+                _ if !arg.starts_with('-') => positional.push(arg),
+                _ => return Err(Error::Validation(format!("Unknown argument {}", arg))),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let command = positional.next();
+        let has_login_args = login.is_some() || password != Secret::Prompt;
+        let command = match command.as_deref() {
+            Some("register") => Some(Command::Register(RegisterArgs {
+                login,
+                access_level,
+                password,
+            })),
+            _ if access_level.is_some() => {
+                return Err(Error::Validation(
+                    "--access-level is accepted only by register".to_string(),
+                ))
+            }
+            Some("passwd") => Some(Command::Passwd(PasswdArgs {
+                user: LoginArgs { login, password },
+                new_password,
+            })),
+            _ if new_password != Secret::Prompt => {
+                return Err(Error::Validation(
+                    "New password is accepted only by passwd".to_string(),
+                ))
+            }
+            Some("init") => Some(Command::Init(RegisterArgs {
+                login,
+                access_level: Some("admin".to_string()),
+                password,
+            })),
+            Some("login") => Some(Command::Login(LoginArgs { login, password })),
+            Some("users") => Some(Command::Users(UsersArgs {
+                admin: LoginArgs { login, password },
+                action: Self::parse_user_action(&mut positional)?,
+            })),
+            _ if has_login_args => return Err(Error::Validation(
+                "Login and password are accepted only by init, register, login, passwd and users"
+                    .to_string(),
+            )),
+            Some("help") => Some(Command::Help),
+            Some(command) => return Err(Error::Validation(format!("Unknown command {}", command))),
+            None => None,
+        };
+        if let Some(arg) = positional.next() {
+            return Err(Error::Validation(format!("Unknown argument {}", arg)));
+        }
+        Ok(Cli {
+            command,
+            config_path,
+            settings,
+        })
+    }
+
+    /// Parses action of `users` command and the user it applies to
+    fn parse_user_action(args: &mut impl Iterator<Item = String>) -> Result<UserAction> {
+        let action = args
+            .next()
+            .ok_or_else(|| Error::Validation("Missing action of users".to_string()))?;
+        let mut user = || {
+            args.next()
+                .ok_or_else(|| Error::Validation(format!("Missing user of {}", action)))
+        };
+        match action.as_str() {
+            "list" => Ok(UserAction::List),
+            "delete" => Ok(UserAction::Delete(user()?)),
+            "set-level" => {
+                let user = user()?;
+                let level = args.next().ok_or_else(|| {
+                    Error::Validation("Missing access level of set-level".to_string())
+                })?;
+                Ok(UserAction::SetAccessLevel(
+                    user,
+                    Self::parse_access_level(&level)?,
+                ))
+            }
+            "reset-password" => Ok(UserAction::ResetPassword(user()?)),
+            _ => Err(Error::Validation(format!(
+                "Unknown action of users {}",
+                action
+            ))),
+        }
+    }
+
+    /// Secret, read from the file descriptor, given as the value of `arg`
+    fn parse_fd(arg: &str, value: &str) -> Result<Secret> {
+        let fd: u32 = Self::parse_number(arg, value)?;
+        Ok(Secret::File(PathBuf::from(format!("/dev/fd/{}", fd))))
+    }
+
+    fn parse_number<T: FromStr>(arg: &str, value: &str) -> Result<T> {
+        value
+            .parse()
+            .map_err(|_| Error::Validation(format!("Invalid value of {}", arg)))
+    }
+
+    fn parse_access_level(level: &str) -> Result<String> {
+        match level {
+            "admin" | "user" => Ok(level.to_string()),
+            _ => Err(Error::Validation(
+                "There is no such access level".to_string(),
+            )),
+        }
+    }
+
+    /// Asks for the command in the numbered menu
+    pub(super) fn from_menu() -> Result<Self> {
+        println!("{}", Self::MENU_MESSAGE);
+        let action = read_stdin().map_err(|e| Error::io("Failed reading user input action", e))?;
+        match action.as_str() {
+            "1" => Ok(Command::Register(RegisterArgs::default())),
+            "2" => Ok(Command::Login(LoginArgs::default())),
+            "3" => Ok(Command::Passwd(PasswdArgs::default())),
+            _ => Err(Error::Validation("Unknown action".to_string())),
+        }
+    }
+
+    pub(super) fn run(&self, config: &Config) -> Result<()> {
+        match self {
+            Command::Init(args) => Registrar::init(config, args),
+            Command::Register(args) => Registrar::register(config, args),
+            Command::Login(args) => Login::login(config, args),
+            Command::Passwd(args) => Login::passwd(config, args),
+            Command::Users(args) => Admin::run(config, args),
+            Command::Help => {
+                println!("{}", Self::USAGE);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::super::config::Settings;
+    use super::super::credentials::StorageKind;
+    use super::{Cli, Command, LoginArgs, PasswdArgs, RegisterArgs, Secret, UserAction, UsersArgs};
+
+    fn parse(args: &[&str]) -> Cli {
+        Command::parse(args.iter().map(|arg| arg.to_string())).unwrap()
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(parse(&[]).command, None);
+        assert_eq!(parse(&["help"]).command, Some(Command::Help));
+        assert_eq!(parse(&["--help"]).command, Some(Command::Help));
+        assert_eq!(
+            parse(&["register"]).command,
+            Some(Command::Register(RegisterArgs::default()))
+        );
+        assert_eq!(
+            parse(&[
+                "register",
+                "--login",
+                "some_login",
+                "--access-level",
+                "admin",
+                "--password-fd",
+                "3"
+            ])
+            .command,
+            Some(Command::Register(RegisterArgs {
+                login: Some("some_login".to_string()),
+                access_level: Some("admin".to_string()),
+                password: Secret::File(PathBuf::from("/dev/fd/3")),
+            }))
+        );
+        assert_eq!(
+            parse(&["init", "--login", "root"]).command,
+            Some(Command::Init(RegisterArgs {
+                login: Some("root".to_string()),
+                access_level: Some("admin".to_string()),
+                password: Secret::Prompt,
+            }))
+        );
+        assert_eq!(
+            parse(&["login", "--password-file", "secret.txt", "--login", "x"]).command,
+            Some(Command::Login(LoginArgs {
+                login: Some("x".to_string()),
+                password: Secret::File(PathBuf::from("secret.txt")),
+            }))
+        );
+        assert_eq!(
+            parse(&["passwd", "--login", "x", "--new-password-fd", "4"]).command,
+            Some(Command::Passwd(PasswdArgs {
+                user: LoginArgs {
+                    login: Some("x".to_string()),
+                    password: Secret::Prompt,
+                },
+                new_password: Secret::File(PathBuf::from("/dev/fd/4")),
+            }))
+        );
+        assert_eq!(
+            parse(&["users", "list", "--login", "root"]).command,
+            Some(Command::Users(UsersArgs {
+                admin: LoginArgs {
+                    login: Some("root".to_string()),
+                    password: Secret::Prompt,
+                },
+                action: UserAction::List,
+            }))
+        );
+        assert_eq!(
+            parse(&["users", "set-level", "bob", "admin"]).command,
+            Some(Command::Users(UsersArgs {
+                admin: LoginArgs::default(),
+                action: UserAction::SetAccessLevel("bob".to_string(), "admin".to_string()),
+            }))
+        );
+        for (action, expected) in [
+            ("delete", UserAction::Delete("bob".to_string())),
+            (
+                "reset-password",
+                UserAction::ResetPassword("bob".to_string()),
+            ),
+        ] {
+            assert_eq!(
+                parse(&["users", action, "bob"]).command,
+                Some(Command::Users(UsersArgs {
+                    admin: LoginArgs::default(),
+                    action: expected,
+                }))
+            );
+        }
+    }
+
+    #[test]
+    fn parse_settings() {
+        assert_eq!(
+            parse(&[
+                "--config",
+                "/etc/md5.json",
+                "login",
+                "--storage",
+                "log",
+                "--credentials",
+                "/var/lib/md5/credentials.log",
+                "--document-dir",
+                "/srv/docs",
+                "--max-attempts",
+                "5",
+                "--max-lockout",
+                "600",
+            ]),
+            Cli {
+                command: Some(Command::Login(LoginArgs::default())),
+                config_path: Some(PathBuf::from("/etc/md5.json")),
+                settings: Settings {
+                    storage: Some(StorageKind::Log),
+                    credentials_path: Some(PathBuf::from("/var/lib/md5/credentials.log")),
+                    document_dir: Some(PathBuf::from("/srv/docs")),
+                    max_attempts: Some(5),
+                    max_lockout_secs: Some(600),
+                    ..Default::default()
+                },
+            }
+        );
+        let menu = parse(&["--password-policy", "policy.json"]);
+        assert_eq!(menu.command, None);
+        assert_eq!(
+            menu.settings.password_policy,
+            Some(PathBuf::from("policy.json"))
+        );
+    }
+
+    #[test]
+    fn reject_invalid_arguments() {
+        let invalid: [&[&str]; 23] = [
+            &["chpasswd"],
+            &["passwd", "--access-level", "admin"],
+            &["passwd", "--new-password-fd", "stdin"],
+            &["login", "--new-password-file", "secret.txt"],
+            &["users"],
+            &["users", "rename", "bob"],
+            &["users", "list", "bob"],
+            &["users", "delete"],
+            &["users", "set-level", "bob"],
+            &["users", "set-level", "bob", "root"],
+            &["users", "reset-password", "bob", "alice"],
+            &["register", "--login"],
+            &["register", "--access-level", "root"],
+            &["login", "--access-level", "admin"],
+            &["login", "--password-fd", "stdin"],
+            &["login", "--password", "somePa@_ss1"],
+            &["login", "some_login"],
+            &["help", "--login", "x"],
+            &["--login", "x"],
+            &["login", "--storage", "xml"],
+            &["login", "--config"],
+            &["login", "--max-attempts", "-1"],
+            &["init", "--access-level", "user"],
+        ];
+        for args in invalid {
+            assert!(
+                Command::parse(args.iter().map(|arg| arg.to_string())).is_err(),
+                "{:?}",
+                args
+            );
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use super::credentials::StorageKind;
+use super::crypto::PasswordHash;
+use super::documents::Documents;
+use super::error::{Error, Result};
+use super::lockout::LockoutPolicy;
+use super::policy::PasswordPolicy;
+
+/// Settings of the application, which may differ between deployments
+#[derive(Debug, Clone)]
+pub(super) struct Config {
+    /// Backend, in which credentials are stored
+    pub(super) storage: StorageKind,
+    /// File of the credentials store. Failed login counters are kept next to it
+    pub(super) credentials_path: PathBuf,
+    /// Work factor of password hashing for new passwords
+    pub(super) hash_iterations: u32,
+    /// How long to wait for another process to release the credentials store
+    pub(super) lock_timeout: Duration,
+    /// Blocking of login after repeated failures
+    pub(super) lockout: LockoutPolicy,
+    /// Requirements to new passwords
+    pub(super) password_policy: PasswordPolicy,
+    /// Documents, shown after login
+    pub(super) documents: Documents,
+}
+
+/// Settings from one source. Unset ones are taken from sources of lower precedence
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct Settings {
+    pub(super) storage: Option<StorageKind>,
+    pub(super) credentials_path: Option<PathBuf>,
+    pub(super) hash_iterations: Option<u32>,
+    /// Seconds to wait for the lock of the credentials store
+    pub(super) lock_timeout_secs: Option<u64>,
+    /// Failed login attempts in a row, after which login is blocked
+    pub(super) max_attempts: Option<u32>,
+    /// Seconds of the first lockout
+    pub(super) lockout_window_secs: Option<u64>,
+    /// How many times each next lockout is longer
+    pub(super) backoff_factor: Option<u32>,
+    /// Seconds of the longest lockout
+    pub(super) max_lockout_secs: Option<u64>,
+    /// JSON file of the password policy
+    pub(super) password_policy: Option<PathBuf>,
+    /// Directory, against which paths of documents are resolved
+    pub(super) document_dir: Option<PathBuf>,
+    /// Document file for each access level
+    pub(super) documents: Option<BTreeMap<String, PathBuf>>,
+}
+
+impl Settings {
+    /// Reads settings from a JSON config file. Relative paths in it are resolved
+    /// against directory of the file, so it does not matter, where the program is run from
+    fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| Error::io(format!("Failed reading config {}", path.display()), e))?;
+        let mut settings: Self = serde_json::from_str(&contents)
+            .map_err(|e| Error::Config(format!("Invalid config {}: {}", path.display(), e)))?;
+
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        if settings.documents.is_some() && settings.document_dir.is_none() {
+            settings.document_dir = Some(PathBuf::new());
+        }
+        for path in vec![
+            &mut settings.credentials_path,
+            &mut settings.password_policy,
+            &mut settings.document_dir,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = base_dir.join(&*path);
+        }
+        Ok(settings)
+    }
+
+    /// Settings, where unset values are taken from `other`
+    fn or(self, other: Self) -> Self {
+        Settings {
+            storage: self.storage.or(other.storage),
+            credentials_path: self.credentials_path.or(other.credentials_path),
+            hash_iterations: self.hash_iterations.or(other.hash_iterations),
+            lock_timeout_secs: self.lock_timeout_secs.or(other.lock_timeout_secs),
+            max_attempts: self.max_attempts.or(other.max_attempts),
+            lockout_window_secs: self.lockout_window_secs.or(other.lockout_window_secs),
+            backoff_factor: self.backoff_factor.or(other.backoff_factor),
+            max_lockout_secs: self.max_lockout_secs.or(other.max_lockout_secs),
+            password_policy: self.password_policy.or(other.password_policy),
+            document_dir: self.document_dir.or(other.document_dir),
+            documents: self.documents.or(other.documents),
+        }
+    }
+}
+
+impl Config {
+    /// Variable with path to the config file
+    const CONFIG_VAR: &'static str = "MD5_CONFIG";
+    /// Variable with credentials storage kind: "json", "log" or "memory"
+    const STORAGE_VAR: &'static str = "MD5_CREDENTIALS_STORAGE";
+    /// Variable with path to the credentials store
+    const CREDENTIALS_PATH_VAR: &'static str = "MD5_CREDENTIALS_PATH";
+    /// Variable with number of PBKDF2 iterations
+    const HASH_ITERATIONS_VAR: &'static str = "MD5_HASH_ITERATIONS";
+    /// Variable with seconds to wait for the lock of the credentials store
+    const LOCK_TIMEOUT_VAR: &'static str = "MD5_LOCK_TIMEOUT";
+    const DEFAULT_LOCK_TIMEOUT_SECS: u64 = 10;
+    /// Variable with failed login attempts, after which login is blocked
+    const MAX_ATTEMPTS_VAR: &'static str = "MD5_MAX_ATTEMPTS";
+    /// Variable with seconds of the first lockout
+    const LOCKOUT_WINDOW_VAR: &'static str = "MD5_LOCKOUT_WINDOW";
+    /// Variable with growth factor of consecutive lockouts
+    const BACKOFF_FACTOR_VAR: &'static str = "MD5_BACKOFF_FACTOR";
+    /// Variable with seconds of the longest lockout
+    const MAX_LOCKOUT_VAR: &'static str = "MD5_MAX_LOCKOUT";
+    /// Variable with path to JSON file of the password policy
+    const PASSWORD_POLICY_VAR: &'static str = "MD5_PASSWORD_POLICY";
+    /// Variable with directory of documents
+    const DOCUMENT_DIR_VAR: &'static str = "MD5_DOCUMENT_DIR";
+
+    /// Combines configuration from command line flags, environment variables and
+    /// the config file, in that order of precedence. Config file is given by `config_path`
+    /// or by the environment
+    pub(super) fn load(config_path: Option<&Path>, flags: Settings) -> Result<Self> {
+        let config_path = match config_path {
+            Some(path) => Some(path.to_path_buf()),
+            None => Self::var::<PathBuf>(Self::CONFIG_VAR)?,
+        };
+        let file = match config_path {
+            Some(path) => Settings::read(&path)?,
+            None => Settings::default(),
+        };
+        Self::from_settings(flags.or(Self::env_settings()?).or(file))
+    }
+
+    /// File of the credentials store, unless the store is kept in memory only
+    pub(super) fn store_path(&self) -> Option<&Path> {
+        Some(self.credentials_path.as_path()).filter(|_| self.storage != StorageKind::Memory)
+    }
+
+    fn env_settings() -> Result<Settings> {
+        Ok(Settings {
+            storage: Self::var(Self::STORAGE_VAR)?,
+            credentials_path: Self::var(Self::CREDENTIALS_PATH_VAR)?,
+            hash_iterations: Self::var(Self::HASH_ITERATIONS_VAR)?,
+            lock_timeout_secs: Self::var(Self::LOCK_TIMEOUT_VAR)?,
+            max_attempts: Self::var(Self::MAX_ATTEMPTS_VAR)?,
+            lockout_window_secs: Self::var(Self::LOCKOUT_WINDOW_VAR)?,
+            backoff_factor: Self::var(Self::BACKOFF_FACTOR_VAR)?,
+            max_lockout_secs: Self::var(Self::MAX_LOCKOUT_VAR)?,
+            password_policy: Self::var(Self::PASSWORD_POLICY_VAR)?,
+            document_dir: Self::var(Self::DOCUMENT_DIR_VAR)?,
+            documents: None,
+        })
+    }
+
+    /// Fills settings, which are not set, with defaults
+    fn from_settings(settings: Settings) -> Result<Self> {
+        let storage = settings.storage.unwrap_or(StorageKind::Json);
+        let hash_iterations = settings
+            .hash_iterations
+            .unwrap_or(PasswordHash::DEFAULT_ITERATIONS);
+        if hash_iterations == 0 {
+            return Err(Error::Config(
+                "Invalid hash iterations: must be positive".to_string(),
+            ));
+        }
+        let lockout = Self::lockout_policy(&settings)?;
+        let password_policy = match settings.password_policy {
+            Some(path) => PasswordPolicy::load(&path)?,
+            None => PasswordPolicy::default(),
+        };
+        Ok(Config {
+            storage,
+            credentials_path: settings
+                .credentials_path
+                .unwrap_or_else(|| PathBuf::from(storage.default_path())),
+            hash_iterations,
+            lock_timeout: Duration::from_secs(
+                settings
+                    .lock_timeout_secs
+                    .unwrap_or(Self::DEFAULT_LOCK_TIMEOUT_SECS),
+            ),
+            lockout,
+            password_policy,
+            documents: Documents::new(settings.document_dir, settings.documents),
+        })
+    }
+
+    /// Lockout rules, where unset values keep defaults
+    fn lockout_policy(settings: &Settings) -> Result<LockoutPolicy> {
+        let default = LockoutPolicy::default();
+        let policy = LockoutPolicy {
+            max_attempts: settings.max_attempts.unwrap_or(default.max_attempts),
+            lockout_window: settings
+                .lockout_window_secs
+                .map_or(default.lockout_window, Duration::from_secs),
+            backoff_factor: settings.backoff_factor.unwrap_or(default.backoff_factor),
+            max_lockout: settings
+                .max_lockout_secs
+                .map_or(default.max_lockout, Duration::from_secs),
+        };
+        if policy.max_attempts == 0 {
+            return Err(Error::Config(
+                "Invalid max attempts: must be positive".to_string(),
+            ));
+        }
+        if policy.backoff_factor == 0 {
+            return Err(Error::Config(
+                "Invalid backoff factor: must be positive".to_string(),
+            ));
+        }
+        if policy.max_lockout < policy.lockout_window {
+            return Err(Error::Config(
+                "Invalid max lockout: must not be shorter than lockout window".to_string(),
+            ));
+        }
+        Ok(policy)
+    }
+
+    fn var<T>(name: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match env::var(name) {
+            Ok(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|e| Error::Config(format!("Invalid {}: {}", name, e))),
+            Err(env::VarError::NotPresent) => Ok(None),
+            Err(e) => Err(Error::Config(format!("Invalid {}: {}", name, e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use super::super::credentials::testing::TempPath;
+    use super::{Config, LockoutPolicy, Settings, StorageKind};
+
+    #[test]
+    fn settings_precedence() {
+        let flags = Settings {
+            credentials_path: Some(PathBuf::from("flags.txt")),
+            ..Default::default()
+        };
+        let env = Settings {
+            credentials_path: Some(PathBuf::from("env.txt")),
+            storage: Some(StorageKind::Log),
+            ..Default::default()
+        };
+        let file = Settings {
+            storage: Some(StorageKind::Memory),
+            hash_iterations: Some(10),
+            ..Default::default()
+        };
+        let config = Config::from_settings(flags.or(env).or(file)).unwrap();
+        assert_eq!(config.credentials_path, Path::new("flags.txt"));
+        assert_eq!(config.storage, StorageKind::Log);
+        assert_eq!(config.hash_iterations, 10);
+    }
+
+    #[test]
+    fn defaults() {
+        let config = Config::from_settings(Settings::default()).unwrap();
+        assert_eq!(config.storage, StorageKind::Json);
+        assert_eq!(config.credentials_path, Path::new("credentials.txt"));
+        assert!(Config::from_settings(Settings {
+            storage: Some(StorageKind::Log),
+            ..Default::default()
+        })
+        .unwrap()
+        .credentials_path
+        .ends_with("credentials.log"));
+        assert!(Config::from_settings(Settings {
+            hash_iterations: Some(0),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn lockout_settings() {
+        let config = Config::from_settings(Settings {
+            max_attempts: Some(5),
+            lockout_window_secs: Some(30),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(config.lockout.max_attempts, 5);
+        assert_eq!(config.lockout.lockout_window, Duration::from_secs(30));
+        assert_eq!(
+            config.lockout.backoff_factor,
+            LockoutPolicy::default().backoff_factor
+        );
+
+        for invalid in [
+            Settings {
+                max_attempts: Some(0),
+                ..Default::default()
+            },
+            Settings {
+                backoff_factor: Some(0),
+                ..Default::default()
+            },
+            Settings {
+                lockout_window_secs: Some(600),
+                max_lockout_secs: Some(60),
+                ..Default::default()
+            },
+        ] {
+            assert!(
+                Config::from_settings(invalid.clone()).is_err(),
+                "{:?}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn read_config_file() {
+        let dir = TempPath::new("config");
+        fs::create_dir_all(&*dir).unwrap();
+        let path = dir.join("md5.json");
+        fs::write(
+            &path,
+            r#"{"storage": "log", "credentials_path": "data/credentials.log",
+                "documents": {"admin": "/srv/admin.txt", "guest": "guest.txt"}}"#,
+        )
+        .unwrap();
+
+        let settings = Settings::read(&path).unwrap();
+        assert_eq!(settings.storage, Some(StorageKind::Log));
+        assert_eq!(
+            settings.credentials_path,
+            Some(dir.join("data/credentials.log"))
+        );
+        assert_eq!(settings.document_dir, Some(dir.to_path_buf()));
+        let config = Config::from_settings(settings).unwrap();
+        assert_eq!(
+            config.documents.path_for("guest").unwrap(),
+            dir.join("guest.txt")
+        );
+        assert_eq!(
+            config.documents.path_for("admin").unwrap(),
+            Path::new("/srv/admin.txt")
+        );
+        assert!(config.documents.path_for("user").is_err());
+
+        for invalid in [r#"{"storage": "xml"}"#, r#"{"credentials": "x"}"#, "["] {
+            fs::write(&path, invalid).unwrap();
+            assert!(Settings::read(&path).is_err(), "{}", invalid);
+        }
+        drop(dir);
+        assert!(Settings::read(&path).is_err());
+    }
+}
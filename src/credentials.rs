@@ -0,0 +1,619 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions, TryLockError};
+use std::io::{ErrorKind, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Serialize;
+
+mod record;
+mod storage;
+#[cfg(test)]
+pub(crate) mod testing;
+
+pub(crate) use record::CredentialRecord;
+#[cfg(test)]
+use storage::MemoryStorage;
+pub(crate) use storage::StorageKind;
+use storage::{Change, CredentialsStorage};
+
+#[allow(non_camel_case_types)]
+pub(super) type MD5_String = String;
+pub(super) type CredentialsMap = BTreeMap<MD5_String, CredentialRecord>;
+
+use super::config::Config;
+use super::crypto::{md5_utf8, md5_utf8_legacy};
+use super::error::{Error, Result};
+use super::lockout::{LockoutPolicy, Lockouts};
+
+/// Owns the credentials store: loads it, looks users up, changes records and saves them back.
+///
+/// Users are identified by login, which is stored hashed, so callers never deal with the keys.
+pub(super) struct CredentialsManager {
+    /// File of the store, failed login counters are kept next to it.
+    /// `None` for the store in memory
+    path: Option<PathBuf>,
+    storage: Box<dyn CredentialsStorage>,
+    credentials: CredentialsMap,
+    /// Changes, which are not saved yet
+    changes: Vec<Change>,
+    _lock: Option<StoreLock>,
+}
+
+impl CredentialsManager {
+    /// Loads credentials from the store, selected by configuration.
+    /// Store file is created on first run
+    ///
+    /// The store is locked until the manager is dropped, so changes of concurrent
+    /// processes are not lost
+    pub(super) fn open(config: &Config) -> Result<Self> {
+        let path = match config.store_path() {
+            Some(path) => path,
+            None => return Self::with_storage(None, config.storage.open(&config.credentials_path)),
+        };
+        let lock = StoreLock::acquire(path, config.lock_timeout)?;
+        prepare_store_file(path)?;
+        let mut manager = Self::with_storage(Some(path), config.storage.open(path))?;
+        manager._lock = Some(lock);
+        Ok(manager)
+    }
+
+    fn with_storage(path: Option<&Path>, mut storage: Box<dyn CredentialsStorage>) -> Result<Self> {
+        let credentials = storage.load()?;
+        Ok(CredentialsManager {
+            path: path.map(Path::to_path_buf),
+            storage,
+            credentials,
+            changes: Vec::new(),
+            _lock: None,
+        })
+    }
+
+    /// Empty store, which is never saved anywhere
+    #[cfg(test)]
+    pub(super) fn in_memory() -> Self {
+        Self::with_storage(None, Box::new(MemoryStorage::default())).unwrap()
+    }
+
+    /// Failed login counters of users of the store. They are loaded and saved while
+    /// the store is locked, so attempts of parallel processes are all counted
+    pub(super) fn lockouts(&self, policy: LockoutPolicy) -> Result<Lockouts> {
+        Lockouts::load(policy, self.path.as_deref())
+    }
+
+    /// Key, under which credentials of the login are stored
+    pub(super) fn login_hash(login: &str) -> MD5_String {
+        md5_utf8(login)
+    }
+
+    /// Key, under which credentials of the login are actually found. Records, created before
+    /// MD5 was fixed, are stored under the legacy digest of the login until they are updated
+    fn find_key(&self, login: &str) -> Option<MD5_String> {
+        let keys = [Self::login_hash(login), md5_utf8_legacy(login)];
+        keys.iter()
+            .find(|key| self.credentials.contains_key(*key))
+            .cloned()
+    }
+
+    /// Whether the user is stored under the legacy login key and needs to be updated
+    pub(super) fn has_legacy_key(&self, login: &str) -> bool {
+        self.find_key(login)
+            .is_some_and(|key| key != Self::login_hash(login))
+    }
+
+    /// Whether there are no users yet
+    pub(super) fn is_empty(&self) -> bool {
+        self.credentials.is_empty()
+    }
+
+    pub(super) fn get(&self, login: &str) -> Option<&CredentialRecord> {
+        self.credentials.get(&self.find_key(login)?)
+    }
+
+    /// Records of all users
+    pub(super) fn records(&self) -> impl Iterator<Item = &CredentialRecord> {
+        self.credentials.values()
+    }
+
+    /// Adds new user and fills login of the record. Returns `false` and leaves the store untouched if such user exists
+    pub(super) fn insert(&mut self, login: &str, mut record: CredentialRecord) -> bool {
+        if self.find_key(login).is_some() {
+            return false;
+        }
+        record.login = Some(login.to_string());
+        let key = Self::login_hash(login);
+        self.changes.push(Change::Put(key.clone(), record.clone()));
+        self.credentials.insert(key, record);
+        true
+    }
+
+    /// Replaces record of existing user and fills its login. Returns `false` if there is no such user.
+    /// Record, stored under the legacy login key, is moved to the current one
+    pub(super) fn update(&mut self, login: &str, mut record: CredentialRecord) -> bool {
+        let old_key = match self.find_key(login) {
+            Some(key) => key,
+            None => return false,
+        };
+        record.login = Some(login.to_string());
+        let key = Self::login_hash(login);
+        if old_key != key {
+            self.credentials.remove(&old_key);
+            self.changes.push(Change::Delete(old_key));
+        }
+        self.changes.push(Change::Put(key.clone(), record.clone()));
+        self.credentials.insert(key, record);
+        true
+    }
+
+    /// Removes user and returns its record
+    pub(super) fn delete(&mut self, login: &str) -> Option<CredentialRecord> {
+        let key = self.find_key(login)?;
+        let record = self.credentials.remove(&key)?;
+        self.changes.push(Change::Delete(key));
+        Some(record)
+    }
+
+    /// Writes all changes to the store
+    pub(super) fn save(&mut self) -> Result<()> {
+        self.storage.save(&self.credentials, &self.changes)?;
+        self.changes.clear();
+        Ok(())
+    }
+}
+
+/// Advisory lock of the credentials store, which is released, when dropped.
+/// It is taken on a separate `.lock` file, as the store itself is replaced on write
+struct StoreLock {
+    _file: fs::File,
+}
+
+impl StoreLock {
+    const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+    fn acquire(store_path: &Path, timeout: Duration) -> Result<Self> {
+        let path = path_with_suffix(store_path, ".lock");
+        let file = private_file_options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| Error::io(format!("Failed opening file {}", path.display()), e))?;
+        let started = Instant::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(StoreLock { _file: file }),
+                Err(TryLockError::WouldBlock) if started.elapsed() < timeout => {
+                    thread::sleep(Self::RETRY_INTERVAL)
+                }
+                Err(TryLockError::WouldBlock) => {
+                    return Err(Error::Locked(format!(
+                        "Credentials store {} is locked by another process. \
+                         Gave up waiting after {} seconds",
+                        store_path.display(),
+                        timeout.as_secs()
+                    )))
+                }
+                Err(TryLockError::Error(e)) => {
+                    return Err(Error::io(
+                        format!("Failed locking file {}", path.display()),
+                        e,
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Creates missing store file, accessible only by its owner. Refuses existing store file,
+/// which other users may read or write
+fn prepare_store_file(path: &Path) -> Result<()> {
+    match fs::metadata(path) {
+        Ok(metadata) => check_store_permissions(path, &metadata),
+        Err(e) if e.kind() == ErrorKind::NotFound => private_file_options()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map(|_| ())
+            .map_err(|e| Error::io(format!("Failed creating file {}", path.display()), e)),
+        Err(e) => Err(Error::io(
+            format!("Failed opening file {}", path.display()),
+            e,
+        )),
+    }
+}
+
+#[cfg(unix)]
+fn check_store_permissions(path: &Path, metadata: &fs::Metadata) -> Result<()> {
+    if metadata.permissions().mode() & 0o006 != 0 {
+        return Err(Error::Policy(format!(
+            "Credentials store {} is accessible by other users. Restrict it with chmod 600",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_store_permissions(_path: &Path, _metadata: &fs::Metadata) -> Result<()> {
+    Ok(())
+}
+
+/// Options for opening files, which are created readable and writable only by the owner
+pub(super) fn private_file_options() -> OpenOptions {
+    #[allow(unused_mut)]
+    let mut options = OpenOptions::new();
+    #[cfg(unix)]
+    options.mode(0o600);
+    options
+}
+
+/// JSON document in a file, which is replaced atomically on write
+pub(super) struct JsonFile {
+    path: PathBuf,
+}
+
+impl JsonFile {
+    pub(super) fn new(path: impl AsRef<Path>) -> Self {
+        JsonFile {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Reads and deserializes the file. Empty file is read as default value,
+    /// missing file is reported as `None`. If the file is corrupted, its backup is read
+    pub(super) fn read<T: DeserializeOwned + Default>(&self) -> Result<Option<T>> {
+        let contents = match Self::read_contents(&self.path)? {
+            Some(contents) => contents,
+            None => return Ok(None),
+        };
+        match Self::parse(&self.path, &contents) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) => {
+                let backup_path = self.sibling_path(".bak");
+                match Self::read_contents(&backup_path) {
+                    Ok(Some(backup)) => match Self::parse(&backup_path, &backup) {
+                        Ok(value) => {
+                            eprintln!("{}. Recovered from {}", e, backup_path.display());
+                            Ok(Some(value))
+                        }
+                        Err(_) => Err(e),
+                    },
+                    _ => Err(e),
+                }
+            }
+        }
+    }
+
+    fn read_contents(path: &Path) -> Result<Option<String>> {
+        let mut contents = String::new();
+        match OpenOptions::new().read(true).open(path) {
+            Ok(mut f) => {
+                f.read_to_string(&mut contents)
+                    .map_err(|e| Error::io(format!("Failed reading file {}", path.display()), e))?;
+                Ok(Some(contents))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::io(
+                format!("Failed opening file {}", path.display()),
+                e,
+            )),
+        }
+    }
+
+    fn parse<T: DeserializeOwned + Default>(path: &Path, contents: &str) -> Result<T> {
+        if contents.is_empty() {
+            Ok(T::default())
+        } else {
+            serde_json::de::from_str(contents)
+                .map_err(|e| Error::storage(format!("Failed deserializing {}", path.display()), e))
+        }
+    }
+
+    /// Serializes value into a temporary file, flushes it to disk and renames it over
+    /// the original one, so the file is never left half-written. Previous contents are
+    /// kept in the `.bak` file
+    pub(super) fn write<T: Serialize>(&self, value: &T) -> Result<()> {
+        let mut contents = Vec::new();
+        serde_json::ser::to_writer(&mut contents, value).map_err(|e| {
+            Error::storage(format!("Failed serializing {}", self.path.display()), e)
+        })?;
+        self.backup()?;
+        Self::replace(&self.path, &self.sibling_path(".tmp"), &contents)
+    }
+
+    /// Copies the file to `.bak`, unless it is corrupted and the backup is needed to recover
+    fn backup(&self) -> Result<()> {
+        let contents = match Self::read_contents(&self.path)? {
+            Some(contents) => contents,
+            None => return Ok(()),
+        };
+        if !contents.is_empty() && serde_json::from_str::<IgnoredAny>(&contents).is_err() {
+            return Ok(());
+        }
+        Self::replace(
+            &self.sibling_path(".bak"),
+            &self.sibling_path(".bak.tmp"),
+            contents.as_bytes(),
+        )
+    }
+
+    /// Atomically replaces contents of `path`, writing them to `tmp_path` first
+    fn replace(path: &Path, tmp_path: &Path, contents: &[u8]) -> Result<()> {
+        let mut f = private_file_options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(tmp_path)
+            .map_err(|e| Error::io(format!("Failed opening file {}", tmp_path.display()), e))?;
+        f.write_all(contents)
+            .and_then(|_| f.sync_all())
+            .map_err(|e| Error::io(format!("Failed writing file {}", tmp_path.display()), e))?;
+        fs::rename(tmp_path, path)
+            .map_err(|e| Error::io(format!("Failed replacing file {}", path.display()), e))?;
+        sync_parent_dir(path)
+    }
+
+    fn sibling_path(&self, extension: &str) -> PathBuf {
+        path_with_suffix(&self.path, extension)
+    }
+}
+
+pub(super) fn path_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Flushes the directory entry of a renamed file to disk
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| Error::io(format!("Failed syncing directory {}", dir.display()), e))
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::super::crypto::md5_utf8_legacy;
+    use super::testing::{record, test_config, TempPath};
+    use super::{
+        Config, CredentialRecord, CredentialsManager, Error, JsonFile, LockoutPolicy, StorageKind,
+    };
+
+    /// Record as it is kept in the store after being put under `login`
+    fn stored(login: &str, level: &str) -> CredentialRecord {
+        let mut record = record(level);
+        record.login = Some(login.to_string());
+        record
+    }
+
+    #[test]
+    fn insert_update_delete() {
+        let mut manager = CredentialsManager::in_memory();
+
+        assert!(manager.get("login").is_none());
+        assert!(!manager.update("login", record("user")));
+        assert!(manager.delete("login").is_none());
+        assert!(manager.insert("login", record("user")));
+        assert!(!manager.insert("login", record("admin")));
+        assert_eq!(manager.get("login"), Some(&stored("login", "user")));
+        assert!(manager.update("login", record("admin")));
+        assert_eq!(manager.get("login"), Some(&stored("login", "admin")));
+        assert!(manager.insert("other_login", record("user")));
+        let mut logins: Vec<_> = manager
+            .records()
+            .map(|record| record.login.as_deref())
+            .collect();
+        logins.sort();
+        assert_eq!(logins, vec![Some("login"), Some("other_login")]);
+        assert_eq!(manager.delete("login"), Some(stored("login", "admin")));
+        assert!(manager.get("login").is_none());
+        assert_eq!(manager.records().count(), 1);
+    }
+
+    #[test]
+    fn legacy_login_key() {
+        let mut manager = CredentialsManager::in_memory();
+        let legacy_key = md5_utf8_legacy("login");
+        manager
+            .credentials
+            .insert(legacy_key.clone(), record("user"));
+
+        assert!(manager.has_legacy_key("login"));
+        assert_eq!(manager.get("login"), Some(&record("user")));
+        assert!(!manager.insert("login", record("admin")));
+
+        assert!(manager.update("login", record("admin")));
+        assert!(!manager.has_legacy_key("login"));
+        assert!(!manager.credentials.contains_key(&legacy_key));
+        assert_eq!(manager.get("login"), Some(&stored("login", "admin")));
+        assert_eq!(manager.credentials.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn create_store_on_first_run() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = TempPath::new("credentials-first-run");
+        let config = test_config(StorageKind::Json, &path);
+
+        let mut manager = CredentialsManager::open(&config).unwrap();
+        assert!(manager.is_empty());
+        let mode = |config: &Config| {
+            fs::metadata(&config.credentials_path)
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o777
+        };
+        assert_eq!(mode(&config), 0o600);
+        manager.insert("login", record("admin"));
+        manager.save().unwrap();
+        drop(manager);
+        assert_eq!(mode(&config), 0o600);
+        assert!(!CredentialsManager::open(&config).unwrap().is_empty());
+
+        fs::set_permissions(&config.credentials_path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(CredentialsManager::open(&config).is_err());
+        fs::set_permissions(&config.credentials_path, fs::Permissions::from_mode(0o640)).unwrap();
+        assert!(CredentialsManager::open(&config).is_ok());
+    }
+
+    #[test]
+    fn store_is_locked_while_open() {
+        let path = TempPath::new("credentials-lock");
+        let config = test_config(StorageKind::Json, &path);
+        let mut manager = CredentialsManager::open(&config).unwrap();
+        assert!(matches!(
+            CredentialsManager::open(&config),
+            Err(Error::Locked(_))
+        ));
+
+        // Other process waits for the lock and sees changes made under it
+        let waiting = {
+            let config = Config {
+                lock_timeout: Duration::from_secs(10),
+                ..config.clone()
+            };
+            thread::spawn(move || CredentialsManager::open(&config).map(|m| m.is_empty()))
+        };
+        thread::sleep(Duration::from_millis(100));
+        manager.insert("login", record("user"));
+        manager.save().unwrap();
+        drop(manager);
+        assert!(!waiting.join().unwrap().unwrap());
+    }
+
+    #[test]
+    fn lockouts_are_counted_under_store_lock() {
+        let path = TempPath::new("credentials-lockouts");
+        let config = Config {
+            lock_timeout: Duration::from_secs(10),
+            lockout: LockoutPolicy {
+                max_attempts: 8,
+                ..Default::default()
+            },
+            ..test_config(StorageKind::Json, &path)
+        };
+        let attempts: Vec<_> = (0..config.lockout.max_attempts)
+            .map(|_| {
+                let config = config.clone();
+                thread::spawn(move || {
+                    let manager = CredentialsManager::open(&config).unwrap();
+                    let mut lockouts = manager.lockouts(config.lockout.clone()).unwrap();
+                    lockouts.check("login").unwrap();
+                    thread::sleep(Duration::from_millis(5));
+                    lockouts.register_failure("login");
+                    lockouts.save().unwrap();
+                })
+            })
+            .collect();
+        for attempt in attempts {
+            attempt.join().unwrap();
+        }
+
+        let manager = CredentialsManager::open(&config).unwrap();
+        let lockouts = manager.lockouts(config.lockout.clone()).unwrap();
+        assert!(lockouts.check("login").is_err());
+        assert!(lockouts.check("other_login").is_ok());
+    }
+
+    #[test]
+    fn lockouts_are_kept_per_store() {
+        let dir = TempPath::new("credentials-lockouts-per-store");
+        fs::create_dir(&dir).unwrap();
+        let memory = test_config(StorageKind::Memory, &dir.join("credentials.txt"));
+        let json = test_config(StorageKind::Json, &dir.join("credentials.txt"));
+        let log = test_config(StorageKind::Log, &dir.join("credentials.log"));
+        let block = |config: &Config| {
+            let manager = CredentialsManager::open(config).unwrap();
+            let mut lockouts = manager.lockouts(config.lockout.clone()).unwrap();
+            for _ in 0..config.lockout.max_attempts {
+                lockouts.register_failure("login");
+            }
+            lockouts.save().unwrap();
+            assert!(lockouts.check("login").is_err());
+        };
+        let is_blocked = |config: &Config| {
+            let manager = CredentialsManager::open(config).unwrap();
+            let lockouts = manager.lockouts(config.lockout.clone()).unwrap();
+            lockouts.check("login").is_err()
+        };
+
+        block(&memory);
+        assert!(!is_blocked(&memory));
+        assert!(!is_blocked(&json));
+        assert_eq!(fs::read_dir(&*dir).unwrap().count(), 2);
+
+        block(&json);
+        assert!(is_blocked(&json));
+        assert!(!is_blocked(&log));
+        assert!(dir.join("credentials.txt.lockout").exists());
+        assert!(!dir.join("credentials.log.lockout").exists());
+    }
+
+    #[test]
+    fn recover_json_file_from_backup() {
+        let path = TempPath::new("json-file");
+        let file = JsonFile::new(&*path);
+        let backup = file.sibling_path(".bak");
+
+        file.write(&vec![1]).unwrap();
+        assert!(!backup.exists());
+        file.write(&vec![2]).unwrap();
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "[1]");
+        assert_eq!(file.read::<Vec<i32>>().unwrap(), Some(vec![2]));
+        assert!(!file.sibling_path(".tmp").exists());
+
+        // Write, interrupted in the middle
+        fs::write(&path, "[3, 4").unwrap();
+        assert_eq!(file.read::<Vec<i32>>().unwrap(), Some(vec![1]));
+        // Corrupted file does not replace the backup
+        file.write(&vec![5]).unwrap();
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "[1]");
+        assert_eq!(file.read::<Vec<i32>>().unwrap(), Some(vec![5]));
+
+        fs::write(&path, "[3, 4").unwrap();
+        fs::write(&backup, "[1").unwrap();
+        assert!(file.read::<Vec<i32>>().is_err());
+    }
+
+    #[test]
+    fn save_and_open() {
+        for storage in [StorageKind::Json, StorageKind::Log] {
+            let path = TempPath::new(&format!("credentials-{:?}", storage));
+            let config = test_config(storage, &path);
+            let mut manager = CredentialsManager::open(&config).unwrap();
+            manager.insert("login", record("user"));
+            manager.insert("other_login", record("user"));
+            manager.save().unwrap();
+            manager.update("login", record("admin"));
+            manager.delete("other_login");
+            manager.save().unwrap();
+            drop(manager);
+
+            let manager = CredentialsManager::open(&config).unwrap();
+            assert_eq!(manager.get("login"), Some(&stored("login", "admin")));
+            assert!(manager.get("other_login").is_none());
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Serialize};
+
+use super::super::crypto::PasswordHash;
+
+/// Credentials of a single user.
+///
+/// Records are stored as `{"version": 2, "login": .., "password": {"scheme": ..}, "access_level": ..,
+/// "must_change_password": .., "reset_token": .., "password_history": [..]}`, where the token is
+/// present only while it is not used. Records of version 1 have no login and reset flag, unversioned
+/// records of the form `[password_hash, access_level]` are still accepted on load as well.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "StoredRecord")]
+pub(crate) struct CredentialRecord {
+    version: u32,
+    /// Login in plain text, as keys are hashed. Unknown for records of older versions,
+    /// until the user logs in
+    pub(crate) login: Option<String>,
+    pub(crate) password: PasswordHash,
+    pub(crate) access_level: String,
+    /// Whether an administrator requested the password to be changed on the next login
+    pub(crate) must_change_password: bool,
+    /// Hash of the one-time token, issued by an administrator. It is accepted in place of
+    /// the password, until the password is changed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) reset_token: Option<PasswordHash>,
+    /// Hashes of previous passwords, the latest first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) password_history: Vec<PasswordHash>,
+}
+
+impl CredentialRecord {
+    const VERSION: u32 = 2;
+
+    /// Record without login, it is filled by the store, when the record is put into it
+    pub(crate) fn new(password: PasswordHash, access_level: String) -> Self {
+        CredentialRecord {
+            version: Self::VERSION,
+            login: None,
+            password,
+            access_level,
+            must_change_password: false,
+            reset_token: None,
+            password_history: Vec::new(),
+        }
+    }
+
+    /// Hashes of the password, the reset token and the previous passwords
+    pub(crate) fn hashes(&self) -> impl Iterator<Item = &PasswordHash> {
+        std::iter::once(&self.password)
+            .chain(&self.reset_token)
+            .chain(&self.password_history)
+    }
+}
+
+/// Any record format, which may be found in the store
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredRecord {
+    Versioned {
+        version: u32,
+        #[serde(default)]
+        login: Option<String>,
+        password: PasswordHash,
+        access_level: String,
+        #[serde(default)]
+        must_change_password: bool,
+        #[serde(default)]
+        reset_token: Option<PasswordHash>,
+        #[serde(default)]
+        password_history: Vec<PasswordHash>,
+    },
+    Unversioned(String, String),
+}
+
+impl TryFrom<StoredRecord> for CredentialRecord {
+    type Error = String;
+
+    fn try_from(record: StoredRecord) -> Result<Self, Self::Error> {
+        match record {
+            StoredRecord::Versioned {
+                version: 1..=Self::VERSION,
+                login,
+                password,
+                access_level,
+                must_change_password,
+                reset_token,
+                password_history,
+            } => Ok(CredentialRecord {
+                login,
+                must_change_password,
+                reset_token,
+                password_history,
+                ..CredentialRecord::new(password, access_level)
+            }),
+            StoredRecord::Versioned { version, .. } => {
+                Err(format!("Unsupported credential record version {}", version))
+            }
+            StoredRecord::Unversioned(password, access_level) => {
+                Ok(CredentialRecord::new(password.parse()?, access_level))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CredentialRecord, PasswordHash};
+
+    #[test]
+    fn load_unversioned_record() {
+        let record: CredentialRecord =
+            serde_json::from_str(r#"["0ae5f1fb68032fed45e041a748e8fcd0","admin"]"#).unwrap();
+        assert_eq!(
+            record,
+            CredentialRecord::new(
+                PasswordHash::Md5 {
+                    hash: "0ae5f1fb68032fed45e041a748e8fcd0".to_string()
+                },
+                "admin".to_string()
+            )
+        );
+        assert!(serde_json::from_str::<CredentialRecord>(r#"["not a hash","admin"]"#).is_err());
+    }
+
+    #[test]
+    fn versioned_record_round_trip() {
+        let record = CredentialRecord {
+            login: Some("some_login".to_string()),
+            must_change_password: true,
+            reset_token: Some(PasswordHash::new("token", 10).unwrap()),
+            password_history: vec![PasswordHash::new("oldPa@_ss1", 10).unwrap()],
+            ..CredentialRecord::new(
+                PasswordHash::new("somePa@_ss1", 10).unwrap(),
+                "user".to_string(),
+            )
+        };
+        let json = serde_json::to_string(&record).unwrap();
+        assert!(json.starts_with(
+            r#"{"version":2,"login":"some_login","password":{"scheme":"pbkdf2-sha256","#
+        ));
+        assert!(json.contains(r#""must_change_password":true,"reset_token":{"#));
+        assert_eq!(
+            serde_json::from_str::<CredentialRecord>(&json).unwrap(),
+            record
+        );
+        assert!(json.contains(r#","password_history":[{"scheme":"pbkdf2-sha256","#));
+        let new_record =
+            CredentialRecord::new(PasswordHash::new("x", 10).unwrap(), "user".to_string());
+        let new_json = serde_json::to_string(&new_record).unwrap();
+        assert!(!new_json.contains("reset_token") && !new_json.contains("password_history"));
+
+        let unsupported = json.replace(r#""version":2"#, r#""version":3"#);
+        assert!(serde_json::from_str::<CredentialRecord>(&unsupported).is_err());
+    }
+
+    #[test]
+    fn load_record_of_version_1() {
+        let record: CredentialRecord = serde_json::from_str(
+            r#"{"version":1,"password":{"scheme":"md5","hash":"0ae5f1fb68032fed45e041a748e8fcd0"},"access_level":"admin"}"#,
+        )
+        .unwrap();
+        assert_eq!(record.login, None);
+        assert!(!record.must_change_password);
+        assert_eq!(record.access_level, "admin");
+    }
+}
@@ -0,0 +1,411 @@
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::super::error::{Error, Result};
+use super::{
+    path_with_suffix, private_file_options, CredentialRecord, CredentialsMap, JsonFile, MD5_String,
+};
+
+/// Single modification of the credentials store
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) enum Change {
+    Put(MD5_String, CredentialRecord),
+    Delete(MD5_String),
+}
+
+impl Change {
+    fn apply(self, credentials: &mut CredentialsMap) {
+        match self {
+            Change::Put(key, record) => {
+                credentials.insert(key, record);
+            }
+            Change::Delete(key) => {
+                credentials.remove(&key);
+            }
+        }
+    }
+}
+
+/// Place, where credentials are persisted
+pub(super) trait CredentialsStorage {
+    fn load(&mut self) -> Result<CredentialsMap>;
+
+    /// Persists `changes` made since the last save. `credentials` is the state after all of them
+    fn save(&mut self, credentials: &CredentialsMap, changes: &[Change]) -> Result<()>;
+}
+
+/// Kind of credentials storage, selected by configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum StorageKind {
+    /// Whole store is a single JSON object, rewritten on each save
+    Json,
+    /// Each change is appended to the file as a separate JSON line, superseded ones
+    /// are dropped from time to time
+    Log,
+    /// Nothing is persisted, the store lives until the process exits
+    Memory,
+}
+
+impl StorageKind {
+    pub(crate) fn default_path(self) -> &'static str {
+        match self {
+            StorageKind::Json | StorageKind::Memory => "credentials.txt",
+            StorageKind::Log => "credentials.log",
+        }
+    }
+
+    pub(super) fn open(self, path: &Path) -> Box<dyn CredentialsStorage> {
+        match self {
+            StorageKind::Json => Box::new(JsonStorage::new(path)),
+            StorageKind::Log => Box::new(LogStorage::new(path)),
+            StorageKind::Memory => Box::new(MemoryStorage::default()),
+        }
+    }
+}
+
+impl FromStr for StorageKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(StorageKind::Json),
+            "log" => Ok(StorageKind::Log),
+            "memory" => Ok(StorageKind::Memory),
+            _ => Err(format!("There is no such credentials storage: {}", s)),
+        }
+    }
+}
+
+/// Credentials in a single JSON object, the format of `credentials.txt`
+pub(super) struct JsonStorage {
+    file: JsonFile,
+}
+
+impl JsonStorage {
+    pub(super) fn new(path: &Path) -> Self {
+        JsonStorage {
+            file: JsonFile::new(path),
+        }
+    }
+}
+
+impl CredentialsStorage for JsonStorage {
+    fn load(&mut self) -> Result<CredentialsMap> {
+        self.file.read()?.ok_or_else(|| {
+            Error::io(
+                format!("Failed opening file {}", self.file.path.display()),
+                io::ErrorKind::NotFound.into(),
+            )
+        })
+    }
+
+    fn save(&mut self, credentials: &CredentialsMap, _changes: &[Change]) -> Result<()> {
+        self.file.write(credentials)
+    }
+}
+
+/// Log of changes, one JSON encoded `Change` per line. Changes are appended, until
+/// they would leave a hash, dropped from the store, in the file or the superseded
+/// changes outnumber the current records. Then the log is rewritten as a snapshot
+pub(super) struct LogStorage {
+    path: PathBuf,
+    /// Length of the complete lines, if the last line was left unfinished by an
+    /// interrupted save. The line is cut off before the next save
+    complete_len: Option<u64>,
+    /// Credentials, as they are saved in the file
+    stored: CredentialsMap,
+    /// Number of changes in the file
+    lines: usize,
+}
+
+impl LogStorage {
+    pub(super) fn new(path: &Path) -> Self {
+        LogStorage {
+            path: path.to_path_buf(),
+            complete_len: None,
+            stored: CredentialsMap::new(),
+            lines: 0,
+        }
+    }
+
+    /// Whether the change leaves a hash of the record, which is stored under its key,
+    /// in superseded changes only
+    fn drops_hashes(stored: &CredentialsMap, change: &Change) -> bool {
+        match change {
+            Change::Delete(_) => true,
+            Change::Put(key, record) => stored.get(key).is_some_and(|old| {
+                old.hashes()
+                    .any(|hash| !record.hashes().any(|kept| kept == hash))
+            }),
+        }
+    }
+
+    fn serialize(&self, changes: &[Change]) -> Result<Vec<u8>> {
+        let mut lines = Vec::new();
+        for change in changes {
+            serde_json::ser::to_writer(&mut lines, change).map_err(|e| {
+                Error::storage(format!("Failed serializing {}", self.path.display()), e)
+            })?;
+            lines.push(b'\n');
+        }
+        Ok(lines)
+    }
+
+    fn append(&mut self, changes: &[Change]) -> Result<()> {
+        let lines = self.serialize(changes)?;
+        let complete_len = self.complete_len;
+        private_file_options()
+            .append(true)
+            .create(true)
+            .open(&self.path)
+            .and_then(|mut f| {
+                if let Some(len) = complete_len {
+                    f.set_len(len)?;
+                }
+                f.write_all(&lines)?;
+                f.sync_all()
+            })
+            .map_err(|e| Error::io(format!("Failed writing file {}", self.path.display()), e))?;
+        self.lines += changes.len();
+        Ok(())
+    }
+
+    /// Atomically replaces the log with a `Put` of each record
+    fn compact(&mut self, credentials: &CredentialsMap) -> Result<()> {
+        let changes: Vec<Change> = credentials
+            .iter()
+            .map(|(key, record)| Change::Put(key.clone(), record.clone()))
+            .collect();
+        let lines = self.serialize(&changes)?;
+        JsonFile::replace(&self.path, &path_with_suffix(&self.path, ".tmp"), &lines)?;
+        self.lines = changes.len();
+        Ok(())
+    }
+}
+
+impl CredentialsStorage for LogStorage {
+    /// Change is saved, when its line is finished, so the last line without the line
+    /// break is skipped. Corrupted lines before it fail the load
+    fn load(&mut self) -> Result<CredentialsMap> {
+        let mut contents = Vec::new();
+        OpenOptions::new()
+            .read(true)
+            .open(&self.path)
+            .map_err(|e| Error::io(format!("Failed opening file {}", self.path.display()), e))?
+            .read_to_end(&mut contents)
+            .map_err(|e| Error::io(format!("Failed reading file {}", self.path.display()), e))?;
+
+        let mut credentials = CredentialsMap::new();
+        let mut complete_len = 0;
+        self.complete_len = None;
+        self.lines = 0;
+        for (index, line) in contents.split_inclusive(|b| *b == b'\n').enumerate() {
+            if !line.ends_with(b"\n") {
+                eprintln!(
+                    "Unfinished line {} of {} is skipped",
+                    index + 1,
+                    self.path.display()
+                );
+                self.complete_len = Some(complete_len as u64);
+                break;
+            }
+            complete_len += line.len();
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            let change: Change = serde_json::de::from_slice(line).map_err(|e| {
+                Error::storage(
+                    format!(
+                        "Failed deserializing {} line {}",
+                        self.path.display(),
+                        index + 1
+                    ),
+                    e,
+                )
+            })?;
+            change.apply(&mut credentials);
+            self.lines += 1;
+        }
+        self.stored = credentials.clone();
+        Ok(credentials)
+    }
+
+    fn save(&mut self, credentials: &CredentialsMap, changes: &[Change]) -> Result<()> {
+        let mut state = self.stored.clone();
+        let mut drops_hashes = false;
+        for change in changes {
+            drops_hashes |= Self::drops_hashes(&state, change);
+            change.clone().apply(&mut state);
+        }
+        if drops_hashes || self.lines + changes.len() > 2 * credentials.len() {
+            self.compact(credentials)?;
+        } else {
+            self.append(changes)?;
+        }
+        self.complete_len = None;
+        self.stored = state;
+        Ok(())
+    }
+}
+
+/// Credentials, which are kept in memory only
+#[derive(Default)]
+pub(super) struct MemoryStorage {
+    credentials: CredentialsMap,
+}
+
+impl CredentialsStorage for MemoryStorage {
+    fn load(&mut self) -> Result<CredentialsMap> {
+        Ok(self.credentials.clone())
+    }
+
+    fn save(&mut self, credentials: &CredentialsMap, _changes: &[Change]) -> Result<()> {
+        self.credentials = credentials.clone();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::super::super::crypto::PasswordHash;
+    use super::super::testing::{record, TempPath};
+    use super::{Change, CredentialsMap, StorageKind};
+
+    fn md5(hash: &str) -> PasswordHash {
+        PasswordHash::Md5 {
+            hash: hash.to_string(),
+        }
+    }
+
+    /// Saves two batches of changes and checks that storage loads the resulting state
+    fn check_round_trip(kind: StorageKind, path: &Path) {
+        let mut storage = kind.open(path);
+        let mut credentials = CredentialsMap::new();
+
+        let changes = vec![
+            Change::Put("first".to_string(), record("user")),
+            Change::Put("second".to_string(), record("user")),
+        ];
+        for change in changes.clone() {
+            change.apply(&mut credentials);
+        }
+        storage.save(&credentials, &changes).unwrap();
+
+        let changes = vec![
+            Change::Put("first".to_string(), record("admin")),
+            Change::Delete("second".to_string()),
+        ];
+        for change in changes.clone() {
+            change.apply(&mut credentials);
+        }
+        storage.save(&credentials, &changes).unwrap();
+
+        assert_eq!(storage.load().unwrap(), credentials);
+        assert_eq!(kind.open(path).load().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn storage_kind_from_str() {
+        assert_eq!("json".parse(), Ok(StorageKind::Json));
+        assert_eq!("log".parse(), Ok(StorageKind::Log));
+        assert_eq!("memory".parse(), Ok(StorageKind::Memory));
+        assert!("sqlite".parse::<StorageKind>().is_err());
+    }
+
+    #[test]
+    fn json_storage_round_trip() {
+        check_round_trip(StorageKind::Json, &TempPath::new("storage-json"));
+    }
+
+    #[test]
+    fn log_storage_round_trip() {
+        let path = TempPath::new("storage-log");
+        check_round_trip(StorageKind::Log, &path);
+        // Deletion compacts the log
+        assert_eq!(fs::read_to_string(&*path).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn log_storage_forgets_dropped_hashes() {
+        let path = TempPath::new("storage-log-compact");
+        let mut storage = StorageKind::Log.open(&path);
+        let mut credentials = CredentialsMap::new();
+        let mut save = |key: &str, change: Option<(&str, &[&str])>| {
+            let change = match change {
+                Some((password, history)) => {
+                    let mut record = record("user");
+                    record.password = md5(password);
+                    record.password_history = history.iter().map(|hash| md5(hash)).collect();
+                    Change::Put(key.to_string(), record)
+                }
+                None => Change::Delete(key.to_string()),
+            };
+            change.clone().apply(&mut credentials);
+            storage.save(&credentials, &[change]).unwrap();
+            fs::read_to_string(&*path).unwrap()
+        };
+
+        save("first", Some(("old", &[])));
+        save("second", Some(("other", &[])));
+        let log = save("first", Some(("new", &["old"])));
+        assert_eq!(log.lines().count(), 3);
+        let log = save("first", Some(("newest", &["new"])));
+        assert_eq!(log.lines().count(), 2);
+        assert!(!log.contains("\"old\""));
+        let log = save("second", None);
+        assert_eq!(log.lines().count(), 1);
+        assert!(!log.contains("\"other\""));
+    }
+
+    #[test]
+    fn log_storage_skips_unfinished_line() {
+        let path = TempPath::new("storage-log-unfinished");
+        check_round_trip(StorageKind::Log, &path);
+        let saved = fs::read(&*path).unwrap();
+        // Line, cut in the middle of a symbol
+        let line = serde_json::to_vec(&Change::Put("третий".to_string(), record("user"))).unwrap();
+        fs::write(&path, [&saved[..], &line[..10]].concat()).unwrap();
+
+        let mut storage = StorageKind::Log.open(&path);
+        let mut credentials = storage.load().unwrap();
+        assert_eq!(credentials.len(), 1);
+        let change = Change::Put("second".to_string(), record("user"));
+        change.clone().apply(&mut credentials);
+        storage.save(&credentials, &[change]).unwrap();
+        assert_eq!(StorageKind::Log.open(&path).load().unwrap(), credentials);
+        assert_eq!(fs::read_to_string(&*path).unwrap().lines().count(), 2);
+
+        // Corrupted line, followed by others, is not skipped
+        fs::write(&path, [&line[..10], b"\n", &saved[..]].concat()).unwrap();
+        assert!(StorageKind::Log.open(&path).load().is_err());
+    }
+
+    #[test]
+    fn memory_storage_round_trip() {
+        let mut storage = StorageKind::Memory.open(&TempPath::new("storage-memory"));
+        let mut credentials = CredentialsMap::new();
+        credentials.insert("first".to_string(), record("user"));
+        storage.save(&credentials, &[]).unwrap();
+        assert_eq!(storage.load().unwrap(), credentials);
+    }
+
+    #[test]
+    fn missing_storage() {
+        assert!(StorageKind::Json
+            .open(&TempPath::new("storage-missing"))
+            .load()
+            .is_err());
+        assert!(StorageKind::Log
+            .open(&TempPath::new("storage-missing"))
+            .load()
+            .is_err());
+    }
+}
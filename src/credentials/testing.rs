@@ -0,0 +1,76 @@
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::super::config::Config;
+use super::super::crypto::PasswordHash;
+use super::super::lockout::Lockouts;
+use super::{path_with_suffix, CredentialRecord, StorageKind};
+
+/// Record with a fixed MD5 password hash
+pub(crate) fn record(level: &str) -> CredentialRecord {
+    let password = PasswordHash::Md5 {
+        hash: "0ae5f1fb68032fed45e041a748e8fcd0".to_string(),
+    };
+    CredentialRecord::new(password, level.to_string())
+}
+
+/// Configuration with the store at `path`, cheap hashing and no waiting for the lock
+pub(crate) fn test_config(storage: StorageKind, path: &Path) -> Config {
+    Config {
+        storage,
+        credentials_path: path.to_path_buf(),
+        hash_iterations: 10,
+        lock_timeout: Duration::from_secs(0),
+        lockout: Default::default(),
+        password_policy: Default::default(),
+        documents: Default::default(),
+    }
+}
+
+/// Path in the temporary directory, unique for the test and the process. The file or
+/// directory is removed, when the path is dropped, together with the files, which
+/// the store keeps next to it
+pub(crate) struct TempPath(PathBuf);
+
+impl TempPath {
+    /// Path, left over from an interrupted run, is cleaned up first
+    pub(crate) fn new(name: &str) -> Self {
+        let path =
+            TempPath(std::env::temp_dir().join(format!("md5-{}-{}", name, std::process::id())));
+        path.remove();
+        path
+    }
+
+    fn remove(&self) {
+        if self.0.is_dir() {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+        for base in [self.0.clone(), path_with_suffix(&self.0, Lockouts::SUFFIX)] {
+            for suffix in ["", ".bak", ".tmp", ".lock"] {
+                let _ = fs::remove_file(path_with_suffix(&base, suffix));
+            }
+        }
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
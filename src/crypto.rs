@@ -0,0 +1,329 @@
+mod md5;
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use unicode_normalization::UnicodeNormalization;
+
+use super::error::{Error, Result};
+
+pub(crate) use self::md5::{md5_utf8, md5_utf8_legacy};
+
+/// Password hash together with the scheme, it was computed with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "scheme")]
+pub(super) enum PasswordHash {
+    /// Unsalted MD5 of the password, which was stored before salted hashing was introduced.
+    /// It was computed with the legacy MD5 implementation, see `md5_utf8_legacy`
+    #[serde(rename = "md5")]
+    Md5 { hash: String },
+    /// PBKDF2-HMAC-SHA256 with a random salt
+    #[serde(rename = "pbkdf2-sha256")]
+    Pbkdf2Sha256 {
+        iterations: u32,
+        #[serde(with = "hex_bytes")]
+        salt: Vec<u8>,
+        #[serde(with = "hex_bytes")]
+        hash: Vec<u8>,
+    },
+}
+
+impl PasswordHash {
+    const PBKDF2_SCHEME: &'static str = "pbkdf2-sha256";
+    pub(super) const DEFAULT_ITERATIONS: u32 = 100_000;
+    const SALT_LEN: usize = 16;
+    const HASH_LEN: usize = 32;
+
+    /// Hashes password with the current scheme and a new random salt
+    pub(super) fn new(password: &str, iterations: u32) -> Result<Self> {
+        let mut salt = vec![0u8; Self::SALT_LEN];
+        getrandom::getrandom(&mut salt)
+            .map_err(|e| Error::io("Failed generating salt", e.into()))?;
+        Ok(Self::with_salt(password, iterations, salt))
+    }
+
+    fn with_salt(password: &str, iterations: u32, salt: Vec<u8>) -> Self {
+        let hash = Self::pbkdf2(&normalize_password(password), iterations, &salt);
+        PasswordHash::Pbkdf2Sha256 {
+            iterations,
+            salt,
+            hash,
+        }
+    }
+
+    fn pbkdf2(password: &str, iterations: u32, salt: &[u8]) -> Vec<u8> {
+        let mut hash = vec![0u8; Self::HASH_LEN];
+        pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut hash);
+        hash
+    }
+
+    /// Checks, whether the password produces the same hash. Salted hashes are computed
+    /// from the normalized password, legacy MD5 ones from the password as it was typed
+    pub(super) fn verify(&self, password: &str) -> bool {
+        match self {
+            PasswordHash::Md5 { hash } => {
+                constant_time_eq(md5_utf8_legacy(password).as_bytes(), hash.as_bytes())
+            }
+            PasswordHash::Pbkdf2Sha256 {
+                iterations,
+                salt,
+                hash,
+            } => constant_time_eq(
+                &Self::pbkdf2(&normalize_password(password), *iterations, salt),
+                hash,
+            ),
+        }
+    }
+
+    /// Hash, which no password matches. Verifying it takes as long as verifying a real hash
+    /// with the same work factor
+    pub(super) fn dummy(iterations: u32) -> Self {
+        PasswordHash::Pbkdf2Sha256 {
+            iterations,
+            salt: vec![0u8; Self::SALT_LEN],
+            hash: Vec::new(),
+        }
+    }
+
+    /// Whether the hash was computed with a scheme, which is no longer used for new passwords
+    pub(super) fn is_legacy(&self) -> bool {
+        matches!(self, PasswordHash::Md5 { .. })
+    }
+}
+
+/// Form, in which hashes were stored in unversioned credential records:
+/// bare MD5 hex digest or `pbkdf2-sha256$<iterations>$<hex salt>$<hex hash>`
+impl fmt::Display for PasswordHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasswordHash::Md5 { hash } => write!(f, "{}", hash),
+            PasswordHash::Pbkdf2Sha256 {
+                iterations,
+                salt,
+                hash,
+            } => write!(
+                f,
+                "{}${}${}${}",
+                Self::PBKDF2_SCHEME,
+                iterations,
+                to_hex(salt),
+                to_hex(hash)
+            ),
+        }
+    }
+}
+
+impl FromStr for PasswordHash {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let err = || format!("Invalid password hash: {}", s);
+        let mut parts = s.split('$');
+        let scheme = parts.next().unwrap_or_default();
+        if scheme != Self::PBKDF2_SCHEME {
+            return match from_hex(scheme) {
+                Some(digest) if digest.len() == 16 && parts.next().is_none() => {
+                    Ok(PasswordHash::Md5 {
+                        hash: s.to_string(),
+                    })
+                }
+                _ => Err(err()),
+            };
+        }
+        let iterations = parts
+            .next()
+            .and_then(|i| i.parse().ok())
+            .filter(|&i| i > 0)
+            .ok_or_else(err)?;
+        let salt = parts.next().and_then(from_hex).ok_or_else(err)?;
+        let hash = parts
+            .next()
+            .and_then(from_hex)
+            .filter(|h| h.len() == Self::HASH_LEN)
+            .ok_or_else(err)?;
+        if parts.next().is_some() {
+            return Err(err());
+        }
+        Ok(PasswordHash::Pbkdf2Sha256 {
+            iterations,
+            salt,
+            hash,
+        })
+    }
+}
+
+/// Serializes byte strings as lowercase hex
+mod hex_bytes {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::to_hex(bytes))
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        super::from_hex(&s).ok_or_else(|| D::Error::custom(format!("invalid hex string: {}", s)))
+    }
+}
+
+/// Brings password to Unicode NFKC form, so equivalent inputs (composed and decomposed
+/// letters, full-width digits, ...) produce the same hash
+pub(crate) fn normalize_password(password: &str) -> String {
+    password.nfkc().collect()
+}
+
+/// Compares byte strings in time, which depends only on their lengths,
+/// so comparison of a secret digest does not reveal the length of the matching prefix
+pub(super) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    std::hint::black_box(diff) == 0
+}
+
+const RESET_TOKEN_LEN: usize = 16;
+
+/// Random secret of `RESET_TOKEN_LEN` bytes in hex, which is given out once and stored hashed
+pub(super) fn reset_token() -> Result<String> {
+    let mut token = [0u8; RESET_TOKEN_LEN];
+    getrandom::getrandom(&mut token)
+        .map_err(|e| Error::io("Failed generating reset token", e.into()))?;
+    Ok(to_hex(&token))
+}
+
+pub(super) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{constant_time_eq, md5_utf8_legacy, normalize_password, reset_token, PasswordHash};
+
+    #[test]
+    fn pbkdf2_sha256_test_vector() {
+        // RFC 7914, section 11
+        let hash = PasswordHash::with_salt("passwd", 1, b"salt".to_vec());
+        assert_eq!(
+            hash.to_string(),
+            "pbkdf2-sha256$1$73616c74$55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc"
+        );
+    }
+
+    #[test]
+    fn verify_password() {
+        let hash = PasswordHash::new("somePa@_ss1", 10).unwrap();
+        assert!(hash.verify("somePa@_ss1"));
+        assert!(!hash.verify("somePa@_ss2"));
+        assert!(!hash.verify(""));
+    }
+
+    #[test]
+    fn equivalent_passwords_have_same_hash() {
+        // "é" composed and decomposed, full-width digit
+        let hash = PasswordHash::new("Pass\u{e9}\u{ff11}!", 10).unwrap();
+        assert!(hash.verify("Pass\u{e9}1!"));
+        assert!(hash.verify("Passe\u{301}1!"));
+        assert!(!hash.verify("Passe1!"));
+        assert_eq!(normalize_password("e\u{301}\u{ff11}"), "\u{e9}1");
+        assert_eq!(normalize_password("somePa@_ss1"), "somePa@_ss1");
+    }
+
+    #[test]
+    fn same_passwords_have_different_hashes() {
+        let first = PasswordHash::new("somePa@_ss1", 10).unwrap();
+        let second = PasswordHash::new("somePa@_ss1", 10).unwrap();
+        assert_ne!(first, second);
+        assert!(second.verify("somePa@_ss1"));
+    }
+
+    #[test]
+    fn reset_tokens_are_random() {
+        let token = reset_token().unwrap();
+        assert_eq!(token.len(), 32);
+        assert!(token.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_ne!(token, reset_token().unwrap());
+    }
+
+    #[test]
+    fn parse_and_format() {
+        let hash = PasswordHash::new("somePa@_ss1", 10).unwrap();
+        assert_eq!(hash.to_string().parse(), Ok(hash.clone()));
+        assert!(hash
+            .to_string()
+            .parse::<PasswordHash>()
+            .unwrap()
+            .verify("somePa@_ss1"));
+
+        let legacy = "0ae5f1fb68032fed45e041a748e8fcd0";
+        assert_eq!(
+            legacy.parse(),
+            Ok(PasswordHash::Md5 {
+                hash: legacy.to_string()
+            })
+        );
+
+        let invalid = [
+            "",
+            "0ae5f1fb68032fed45e041a748e8fcd",
+            "0ae5f1fb68032fed45e041a748e8fcd0$",
+            "pbkdf2-sha256$0$73616c74$55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc",
+            "pbkdf2-sha256$1$73616c7$55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc",
+            "pbkdf2-sha256$1$73616c74$55ac046e",
+            "pbkdf2-sha256$1$73616c74$55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc$",
+            "pbkdf2-sha1$1$73616c74$55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc",
+        ];
+        for s in invalid {
+            assert!(s.parse::<PasswordHash>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn constant_time_comparison() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"digest", b"digest"));
+        assert!(!constant_time_eq(b"digest", b"digesT"));
+        assert!(!constant_time_eq(b"digest", b"Digest"));
+        assert!(!constant_time_eq(b"digest", b"digest1"));
+    }
+
+    #[test]
+    fn dummy_hash_matches_nothing() {
+        let dummy = PasswordHash::dummy(10);
+        assert!(!dummy.verify(""));
+        assert!(!dummy.verify("somePa@_ss1"));
+    }
+
+    #[test]
+    fn verify_legacy_md5() {
+        let hash = PasswordHash::Md5 {
+            hash: md5_utf8_legacy("somePa@_ss1"),
+        };
+        assert!(hash.is_legacy());
+        assert!(hash.verify("somePa@_ss1"));
+        assert!(!hash.verify("somePa@_ss2"));
+
+        // Legacy hashes were computed from the raw input
+        let hash = PasswordHash::Md5 {
+            hash: md5_utf8_legacy("Passe\u{301}1!"),
+        };
+        assert!(hash.verify("Passe\u{301}1!"));
+        assert!(!hash.verify("Pass\u{e9}1!"));
+        assert!(!PasswordHash::new("somePa@_ss1", 10).unwrap().is_legacy());
+    }
+}
@@ -0,0 +1,410 @@
+use super::to_hex;
+
+/// Which implementation of MD5 to run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variant {
+    /// MD5 as specified in RFC 1321
+    Rfc1321,
+    /// The first implementation of this crate, which did not add the saved value of register C
+    /// back after each block. Its digests are only needed to check credentials created with it
+    Legacy,
+}
+
+/// MD5 digest of the string as lowercase hex (RFC 1321)
+pub(crate) fn md5_utf8(smsg: &str) -> String {
+    let mut md5 = Md5::new();
+    md5.update(smsg.as_bytes());
+    to_hex(&md5.finalize())
+}
+
+/// Digest of the string, computed by the legacy implementation (see `Variant::Legacy`)
+pub(crate) fn md5_utf8_legacy(smsg: &str) -> String {
+    let mut md5 = Md5::with_variant(Variant::Legacy);
+    md5.update(smsg.as_bytes());
+    to_hex(&md5.finalize())
+}
+
+/// Incremental MD5 context: message is fed by parts with `update`
+/// and processed by 64-byte blocks as soon as they are complete
+#[derive(Debug, Clone)]
+pub(crate) struct Md5 {
+    variant: Variant,
+    /// Регистры A, B, C, D
+    state: [u32; 4],
+    /// Неполный блок, ожидающий следующих данных
+    buffer: [u8; 64],
+    buffer_len: usize,
+    /// Длина сообщения в байтах
+    length: u64,
+}
+
+impl Default for Md5 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Md5 {
+    pub(crate) fn new() -> Self {
+        Self::with_variant(Variant::Rfc1321)
+    }
+
+    fn with_variant(variant: Variant) -> Self {
+        // Инициализация буфера
+        /* Буфер из четырех слов (A, B, C, D) используется для промежуточных вычислений.
+        Порядок байтов little-endian
+        Здесь каждый из A, B, C, D является 32-битным регистром. */
+        Md5 {
+            variant,
+            state: [
+                0x67452301u32, // word A: 01 23 45 67
+                0xefcdab89u32, // word B: 89 ab cd ef
+                0x98badcfeu32, // word C: fe dc ba 98
+                0x10325476u32, // word D: 76 54 32 10
+            ],
+            buffer: [0u8; 64],
+            buffer_len: 0,
+            length: 0,
+        }
+    }
+
+    /// Adds the next part of the message
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        self.length = self.length.wrapping_add(data.len() as u64);
+
+        // Дополнение неполного блока
+        if self.buffer_len > 0 {
+            let n = data.len().min(64 - self.buffer_len);
+            self.buffer[self.buffer_len..self.buffer_len + n].copy_from_slice(&data[..n]);
+            self.buffer_len += n;
+            data = &data[n..];
+            if self.buffer_len < 64 {
+                return;
+            }
+            let block = self.buffer;
+            self.process_block(&block);
+            self.buffer_len = 0;
+        }
+
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            self.process_block(block);
+        }
+        let rest = blocks.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffer_len = rest.len();
+    }
+
+    /// Pads the message and returns its digest
+    pub(crate) fn finalize(mut self) -> [u8; 16] {
+        // Длина сообщения в битах по модулю 2^64
+        let bitcount = self.length.wrapping_mul(8);
+
+        // Добавление битов заполнения: единичный бит, затем нулевые до 448 бит по модулю 512
+        let mut padding = [0u8; 64];
+        padding[0] = 0b10000000;
+        let padding_len = if self.buffer_len < 56 {
+            56 - self.buffer_len
+        } else {
+            120 - self.buffer_len
+        };
+        self.update(&padding[..padding_len]);
+
+        // Добавление длины (64 бит, little-endian) до 512 бит
+        self.update(&bitcount.to_le_bytes());
+
+        // Результат - регистры A, B, C, D в порядке little-endian
+        let mut digest = [0u8; 16];
+        for (bytes, register) in digest.chunks_exact_mut(4).zip(&self.state) {
+            bytes.copy_from_slice(&register.to_le_bytes());
+        }
+        digest
+    }
+
+    /* Обработка блока из 16 слов. (поскольку 1 слово составляет 4 байта, то 16 слов составляют 64 байта) */
+    #[allow(non_snake_case)]
+    fn process_block(&mut self, block: &[u8]) {
+        /* Определение 4ых вспомогательных функций */
+        // Введём функции от трёх параметров — слов, результатом также будет слово
+        let F = |X: u32, Y: u32, Z: u32| -> u32 { X & Y | !X & Z };
+        let G = |X: u32, Y: u32, Z: u32| -> u32 { X & Z | Y & !Z };
+        let H = |X: u32, Y: u32, Z: u32| -> u32 { X ^ Y ^ Z };
+        let I = |X: u32, Y: u32, Z: u32| -> u32 { Y ^ (X | !Z) };
+
+        /* На этом шаге используется таблица T [1 ... 64] из 64 элементов, построенная на основе синусоидальной функции. */
+        let T = [
+            0x00000000, // разрешить использование в качестве 1-индексированной таблицы
+            0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613,
+            0xfd469501, 0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193,
+            0xa679438e, 0x49b40821, 0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d,
+            0x02441453, 0xd8a1e681, 0xe7d3fbc8, 0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed,
+            0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a, 0xfffa3942, 0x8771f681, 0x6d9d6122,
+            0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70, 0x289b7ec6, 0xeaa127fa,
+            0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665, 0xf4292244,
+            0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+            0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb,
+            0xeb86d391,
+        ];
+
+        let [mut A, mut B, mut C, mut D] = self.state;
+
+        /* Копирование блока в Х. Слова записаны в порядке little-endian независимо от платформы */
+        let mut X = [0u32; 16];
+        for (word, bytes) in X.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        /* Сохранить регистры A, B, C, D */
+        let AA = A;
+        let BB = B;
+        let CC = C;
+        let DD = D;
+
+        /* Round 1.  Пусть [abcd k s i] обозначают операцию
+        a = b + ((a + F(b,c,d) + X[k] + T[i]) <<< s). */
+        macro_rules! op1 { // Декларативные макросы(макросы на примере)
+            ($a:ident,$b:ident,$c:ident,$d:ident,$k:expr,$s:expr,$i:expr) => { // идентификация и выражение
+            // pub const fn wrapping_sub(self, rhs: usize) -> usize
+            // Упаковочное (модульное) дополнение. Вычисляет self + rhs, охватывая границу типа.
+                $a = $b.wrapping_add(
+                    ($a.wrapping_add(F($b, $c, $d))
+                        .wrapping_add(X[$k])
+                        .wrapping_add(T[$i]))
+                    .rotate_left($s), // битовый сдвиг
+                /*  Поворачивает фрагмент на месте таким образом, что первые средние элементы фрагмента перемещаются
+                    в конец, а последние элементы self.len () - mid перемещаются вперед. После вызова rotate_left элемент,
+                    ранее находившийся в середине индекса, станет первым элементом в срезе.     */
+                )
+            };
+        }
+
+        /* Выполнить следующие 16 операций. */
+        op1!(A, B, C, D, 0, 7, 1);
+        op1!(D, A, B, C, 1, 12, 2);
+        op1!(C, D, A, B, 2, 17, 3);
+        op1!(B, C, D, A, 3, 22, 4);
+
+        op1!(A, B, C, D, 4, 7, 5);
+        op1!(D, A, B, C, 5, 12, 6);
+        op1!(C, D, A, B, 6, 17, 7);
+        op1!(B, C, D, A, 7, 22, 8);
+
+        op1!(A, B, C, D, 8, 7, 9);
+        op1!(D, A, B, C, 9, 12, 10);
+        op1!(C, D, A, B, 10, 17, 11);
+        op1!(B, C, D, A, 11, 22, 12);
+
+        op1!(A, B, C, D, 12, 7, 13);
+        op1!(D, A, B, C, 13, 12, 14);
+        op1!(C, D, A, B, 14, 17, 15);
+        op1!(B, C, D, A, 15, 22, 16);
+
+        /* Round 2. Пусть [abcd k s i] обозначают операцию
+        a = b + ((a + G(b,c,d) + X[k] + T[i]) <<< s). */
+        macro_rules! op2 {
+            ($a:ident,$b:ident,$c:ident,$d:ident,$k:expr,$s:expr,$i:expr) => {
+                $a = $b.wrapping_add(
+                    ($a.wrapping_add(G($b, $c, $d))
+                        .wrapping_add(X[$k])
+                        .wrapping_add(T[$i]))
+                    .rotate_left($s),
+                )
+            };
+        }
+
+        /* Выполнить следующие 16 операций. */
+        op2!(A, B, C, D, 1, 5, 17);
+        op2!(D, A, B, C, 6, 9, 18);
+        op2!(C, D, A, B, 11, 14, 19);
+        op2!(B, C, D, A, 0, 20, 20);
+
+        op2!(A, B, C, D, 5, 5, 21);
+        op2!(D, A, B, C, 10, 9, 22);
+        op2!(C, D, A, B, 15, 14, 23);
+        op2!(B, C, D, A, 4, 20, 24);
+
+        op2!(A, B, C, D, 9, 5, 25);
+        op2!(D, A, B, C, 14, 9, 26);
+        op2!(C, D, A, B, 3, 14, 27);
+        op2!(B, C, D, A, 8, 20, 28);
+
+        op2!(A, B, C, D, 13, 5, 29);
+        op2!(D, A, B, C, 2, 9, 30);
+        op2!(C, D, A, B, 7, 14, 31);
+        op2!(B, C, D, A, 12, 20, 32);
+
+        /* Round 3. Пусть [abcd k s t] обозначают операцию
+        a = b + ((a + H(b,c,d) + X[k] + T[i]) <<< s). */
+        macro_rules! op3 {
+            ($a:ident,$b:ident,$c:ident,$d:ident,$k:expr,$s:expr,$i:expr) => {
+                $a = $b.wrapping_add(
+                    ($a.wrapping_add(H($b, $c, $d))
+                        .wrapping_add(X[$k])
+                        .wrapping_add(T[$i]))
+                    .rotate_left($s),
+                )
+            };
+        }
+
+        /* Выполнить следующие 16 операций. */
+        op3!(A, B, C, D, 5, 4, 33);
+        op3!(D, A, B, C, 8, 11, 34);
+        op3!(C, D, A, B, 11, 16, 35);
+        op3!(B, C, D, A, 14, 23, 36);
+
+        op3!(A, B, C, D, 1, 4, 37);
+        op3!(D, A, B, C, 4, 11, 38);
+        op3!(C, D, A, B, 7, 16, 39);
+        op3!(B, C, D, A, 10, 23, 40);
+
+        op3!(A, B, C, D, 13, 4, 41);
+        op3!(D, A, B, C, 0, 11, 42);
+        op3!(C, D, A, B, 3, 16, 43);
+        op3!(B, C, D, A, 6, 23, 44);
+
+        op3!(A, B, C, D, 9, 4, 45);
+        op3!(D, A, B, C, 12, 11, 46);
+        op3!(C, D, A, B, 15, 16, 47);
+        op3!(B, C, D, A, 2, 23, 48);
+
+        /* Round 4. Пусть [abcd k s t] обозначают операцию
+        a = b + ((a + I(b,c,d) + X[k] + T[i]) <<< s). */
+        macro_rules! op4 {
+            ($a:ident,$b:ident,$c:ident,$d:ident,$k:expr,$s:expr,$i:expr) => {
+                $a = $b.wrapping_add(
+                    ($a.wrapping_add(I($b, $c, $d))
+                        .wrapping_add(X[$k])
+                        .wrapping_add(T[$i]))
+                    .rotate_left($s),
+                )
+            };
+        }
+
+        /* Выполнить следующие 16 операций. */
+        op4!(A, B, C, D, 0, 6, 49);
+        op4!(D, A, B, C, 7, 10, 50);
+        op4!(C, D, A, B, 14, 15, 51);
+        op4!(B, C, D, A, 5, 21, 52);
+
+        op4!(A, B, C, D, 12, 6, 53);
+        op4!(D, A, B, C, 3, 10, 54);
+        op4!(C, D, A, B, 10, 15, 55);
+        op4!(B, C, D, A, 1, 21, 56);
+
+        op4!(A, B, C, D, 8, 6, 57);
+        op4!(D, A, B, C, 15, 10, 58);
+        op4!(C, D, A, B, 6, 15, 59);
+        op4!(B, C, D, A, 13, 21, 60);
+
+        op4!(A, B, C, D, 4, 6, 61);
+        op4!(D, A, B, C, 11, 10, 62);
+        op4!(C, D, A, B, 2, 15, 63);
+        op4!(B, C, D, A, 9, 21, 64);
+
+        /* . . . увеличить каждый из четырех регистров на значение
+        это было до того, как этот блок был запущен.) */
+
+        A = A.wrapping_add(AA);
+        B = B.wrapping_add(BB);
+        if self.variant == Variant::Rfc1321 {
+            C = C.wrapping_add(CC);
+        }
+        D = D.wrapping_add(DD);
+
+        self.state = [A, B, C, D];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::to_hex;
+    use super::{md5_utf8, md5_utf8_legacy, Md5};
+
+    #[test]
+    fn rfc1321_test_suite() {
+        // RFC 1321, appendix A.5
+        let suite = [
+            ("", "d41d8cd98f00b204e9800998ecf8427e"),
+            ("a", "0cc175b9c0f1b6a831c399e269772661"),
+            ("abc", "900150983cd24fb0d6963f7d28e17f72"),
+            ("message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+            (
+                "abcdefghijklmnopqrstuvwxyz",
+                "c3fcd3d76192e4007dfb496cca67e13b",
+            ),
+            (
+                "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
+                "d174ab98d277d9f5a5611c2c9f419d9f",
+            ),
+            (
+                "12345678901234567890123456789012345678901234567890123456789012345678901234567890",
+                "57edf4a22be3c955ac49da2e2107b67a",
+            ),
+        ];
+        for (msg, digest) in suite {
+            assert_eq!(md5_utf8(msg), digest, "{:?}", msg);
+        }
+    }
+
+    #[test]
+    fn padding_boundaries() {
+        // Messages, for which padding spills into the next block
+        assert_eq!(
+            md5_utf8(&"a".repeat(55)),
+            "ef1772b6dff9a122358552954ad0df65"
+        );
+        assert_eq!(
+            md5_utf8(&"a".repeat(56)),
+            "3b0c8ac703f828b04c6c197006d17218"
+        );
+        assert_eq!(
+            md5_utf8(&"a".repeat(64)),
+            "014842d480b571495a4a0363793f7367"
+        );
+    }
+
+    #[test]
+    fn legacy_digests() {
+        // Digests, which were produced before C register was fixed
+        assert_eq!(md5_utf8_legacy(""), "d41d8cd98f00b204eba34effecf8427e");
+        assert_eq!(md5_utf8_legacy("abc"), "900150983cd24fb0d8b984e428e17f72");
+        assert_eq!(
+            md5_utf8_legacy(
+                "12345678901234567890123456789012345678901234567890123456789012345678901234567890"
+            ),
+            "e88e1ee47c81948e77da43322aeb66b2"
+        );
+    }
+
+    #[test]
+    fn incremental_update() {
+        let msg =
+            "12345678901234567890123456789012345678901234567890123456789012345678901234567890";
+        for chunk_len in 1..=msg.len() {
+            let mut md5 = Md5::new();
+            for chunk in msg.as_bytes().chunks(chunk_len) {
+                md5.update(chunk);
+            }
+            assert_eq!(
+                to_hex(&md5.finalize()),
+                "57edf4a22be3c955ac49da2e2107b67a",
+                "chunk length {}",
+                chunk_len
+            );
+        }
+
+        let mut md5 = Md5::new();
+        md5.update(b"");
+        assert_eq!(to_hex(&md5.finalize()), "d41d8cd98f00b204e9800998ecf8427e");
+    }
+
+    #[test]
+    fn long_message() {
+        let block = [b'a'; 1000];
+        let mut md5 = Md5::new();
+        for _ in 0..1000 {
+            md5.update(&block);
+        }
+        assert_eq!(to_hex(&md5.finalize()), "7707d6ae4e027c70eea2a935c2296f21");
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use super::error::{Error, Result};
+
+/// Documents which are shown to the user after successful login, depending on access level
+#[derive(Debug, Clone)]
+pub(super) struct Documents {
+    /// Directory, against which relative paths of documents are resolved
+    dir: PathBuf,
+    /// Document for each access level
+    files: BTreeMap<String, PathBuf>,
+}
+
+impl Default for Documents {
+    fn default() -> Self {
+        Self::new(None, None)
+    }
+}
+
+impl Documents {
+    const ADMIN_DOCUMENT_PATH: &'static str = "admin_dock.txt";
+    const USER_DOCUMENT_PATH: &'static str = "user_dock.txt";
+
+    /// Documents in `dir` (current directory by default), mapped to access levels by `files`
+    /// (`admin_dock.txt` and `user_dock.txt` by default)
+    pub(super) fn new(dir: Option<PathBuf>, files: Option<BTreeMap<String, PathBuf>>) -> Self {
+        let files = files.unwrap_or_else(|| {
+            [
+                ("admin", Self::ADMIN_DOCUMENT_PATH),
+                ("user", Self::USER_DOCUMENT_PATH),
+            ]
+            .iter()
+            .map(|(level, path)| (level.to_string(), PathBuf::from(path)))
+            .collect()
+        });
+        Documents {
+            dir: dir.unwrap_or_default(),
+            files,
+        }
+    }
+
+    /// Prints document of the access level to stdout with numbered lines
+    pub(super) fn print(&self, access_level: &str) -> Result<()> {
+        let path = self.path_for(access_level)?;
+        let f = File::open(&path)
+            .map_err(|e| Error::io(format!("Failed opening file {}", path.display()), e))?;
+        Self::write_numbered(BufReader::new(f), io::stdout().lock())
+            .map_err(|e| Error::io(format!("Failed printing document {}", path.display()), e))
+    }
+
+    pub(super) fn path_for(&self, access_level: &str) -> Result<PathBuf> {
+        match self.files.get(access_level) {
+            Some(path) => Ok(self.dir.join(path)),
+            None => Err(Error::Config(format!(
+                "There is no document for access level \"{}\"",
+                access_level
+            ))),
+        }
+    }
+
+    fn write_numbered(reader: impl BufRead, mut writer: impl Write) -> io::Result<()> {
+        for (index, line) in reader.lines().enumerate() {
+            writeln!(writer, "{}. {}", index + 1, line?)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::Documents;
+
+    #[test]
+    fn document_for_each_access_level() {
+        let documents = Documents::default();
+        assert_eq!(
+            documents.path_for("admin").unwrap(),
+            Path::new(Documents::ADMIN_DOCUMENT_PATH)
+        );
+        assert_eq!(
+            documents.path_for("user").unwrap(),
+            Path::new(Documents::USER_DOCUMENT_PATH)
+        );
+        assert!(documents.path_for("guest").is_err());
+        assert!(documents.path_for("").is_err());
+    }
+
+    #[test]
+    fn configured_documents() {
+        let documents = Documents::new(Some(PathBuf::from("/srv/docs")), None);
+        assert_eq!(
+            documents.path_for("admin").unwrap(),
+            Path::new("/srv/docs/admin_dock.txt")
+        );
+
+        let files = [("guest", "guest.txt"), ("admin", "/etc/admin.txt")]
+            .iter()
+            .map(|(level, path)| (level.to_string(), PathBuf::from(path)))
+            .collect();
+        let documents = Documents::new(Some(PathBuf::from("/srv/docs")), Some(files));
+        assert_eq!(
+            documents.path_for("guest").unwrap(),
+            Path::new("/srv/docs/guest.txt")
+        );
+        assert_eq!(
+            documents.path_for("admin").unwrap(),
+            Path::new("/etc/admin.txt")
+        );
+        assert!(documents.path_for("user").is_err());
+    }
+
+    #[test]
+    fn document_lines_are_numbered() {
+        let mut out = Vec::new();
+        Documents::write_numbered("first\nsecond\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "1. first\n2. second\n");
+    }
+}
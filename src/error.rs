@@ -0,0 +1,156 @@
+use std::{error, fmt, io};
+
+use super::policy::Rule;
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+/// Error of the application. Each kind of failure is reported with its own process exit code
+#[derive(Debug)]
+pub(crate) enum Error {
+    /// Reading or writing a file or the terminal failed
+    Io { context: String, source: io::Error },
+    /// Stored data is corrupted or can not be serialized
+    Storage {
+        context: String,
+        source: Option<Box<dyn error::Error + Send + Sync>>,
+    },
+    /// User input is invalid
+    Validation(String),
+    /// New password violates rules of the password policy
+    WeakPassword(Vec<Rule>),
+    /// Login or password is wrong
+    Authentication(String),
+    /// Action is refused by security policy
+    Policy(String),
+    /// Configuration of the application is invalid
+    Config(String),
+    /// Store is locked by another process for too long
+    Locked(String),
+}
+
+impl Error {
+    pub(crate) fn io(context: impl Into<String>, source: io::Error) -> Self {
+        Error::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    pub(crate) fn storage(
+        context: impl Into<String>,
+        source: impl Into<Box<dyn error::Error + Send + Sync>>,
+    ) -> Self {
+        Error::Storage {
+            context: context.into(),
+            source: Some(source.into()),
+        }
+    }
+
+    /// Exit code of the process, which failed with this error
+    pub(crate) fn exit_code(&self) -> i32 {
+        match self {
+            Error::Validation(_) | Error::WeakPassword(_) => 2,
+            Error::Authentication(_) => 3,
+            Error::Policy(_) => 4,
+            Error::Storage { .. } => 5,
+            Error::Io { .. } => 6,
+            Error::Config(_) => 7,
+            Error::Locked(_) => 8,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { context, source } => write!(f, "{}: {}", context, source),
+            Error::Storage {
+                context,
+                source: Some(source),
+            } => write!(f, "{}: {}", context, source),
+            Error::Storage {
+                context,
+                source: None,
+            } => write!(f, "{}", context),
+            Error::WeakPassword(violations) => {
+                write!(f, "Password does not satisfy the policy")?;
+                for (i, violation) in violations.iter().enumerate() {
+                    write!(f, "{} {}", if i == 0 { ":" } else { ";" }, violation)?;
+                }
+                Ok(())
+            }
+            Error::Validation(msg)
+            | Error::Authentication(msg)
+            | Error::Policy(msg)
+            | Error::Config(msg)
+            | Error::Locked(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Storage {
+                source: Some(source),
+                ..
+            } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+    use std::io;
+
+    use super::{Error, Rule};
+
+    #[test]
+    fn display_and_source() {
+        let err = Error::io(
+            "Failed opening file credentials.txt",
+            io::Error::new(io::ErrorKind::NotFound, "not found"),
+        );
+        assert_eq!(
+            err.to_string(),
+            "Failed opening file credentials.txt: not found"
+        );
+        assert!(err.source().is_some());
+
+        let err = Error::Validation("Login is equal to password".to_string());
+        assert_eq!(err.to_string(), "Login is equal to password");
+        assert!(err.source().is_none());
+
+        let err = Error::WeakPassword(vec![Rule::Uppercase, Rule::NoWhitespace]);
+        assert_eq!(
+            err.to_string(),
+            "Password does not satisfy the policy: \
+             Password has no upper case letters; Password has spaces"
+        );
+        assert_eq!(
+            err.exit_code(),
+            Error::Validation(String::new()).exit_code()
+        );
+    }
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let errors = [
+            Error::io("", io::Error::from(io::ErrorKind::Other)),
+            Error::storage("", "corrupted"),
+            Error::Validation(String::new()),
+            Error::Authentication(String::new()),
+            Error::Policy(String::new()),
+            Error::Config(String::new()),
+            Error::Locked(String::new()),
+        ];
+        let mut codes: Vec<_> = errors.iter().map(Error::exit_code).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert!(!codes.contains(&0));
+    }
+}
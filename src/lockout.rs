@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::credentials::{path_with_suffix, JsonFile};
+use super::error::{Error, Result};
+
+/// Rules of blocking login attempts after repeated failures
+#[derive(Debug, Clone)]
+pub(super) struct LockoutPolicy {
+    /// Number of failed attempts in a row, after which login is blocked
+    pub(super) max_attempts: u32,
+    /// Duration of the first lockout
+    pub(super) lockout_window: Duration,
+    /// Each next lockout lasts `backoff_factor` times longer than the previous one
+    pub(super) backoff_factor: u32,
+    /// Upper bound of lockout duration
+    pub(super) max_lockout: Duration,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        LockoutPolicy {
+            max_attempts: 3,
+            lockout_window: Duration::from_secs(60),
+            backoff_factor: 2,
+            max_lockout: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl LockoutPolicy {
+    /// Duration of lockout number `lockouts` (starting from 1)
+    fn lockout_duration(&self, lockouts: u32) -> Duration {
+        let factor = self
+            .backoff_factor
+            .checked_pow(lockouts.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.lockout_window
+            .checked_mul(factor)
+            .map_or(self.max_lockout, |d| d.min(self.max_lockout))
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+struct FailureRecord {
+    /// Failed attempts since the last lockout or successful login
+    failed_attempts: u32,
+    /// Lockouts since the last successful login
+    lockouts: u32,
+    /// Unix time in seconds, until which login is blocked
+    locked_until: u64,
+}
+
+/// Failed login counters, persisted per hashed login next to the credentials file
+pub(super) struct Lockouts {
+    policy: LockoutPolicy,
+    /// `None`, if the counters live in memory only, like the store they belong to
+    file: Option<JsonFile>,
+    records: BTreeMap<String, FailureRecord>,
+}
+
+impl Lockouts {
+    pub(super) const SUFFIX: &'static str = ".lockout";
+
+    /// Loads counters stored next to `credentials_path`. Without the path the counters
+    /// start empty and are never persisted
+    pub(super) fn load(policy: LockoutPolicy, credentials_path: Option<&Path>) -> Result<Self> {
+        let file = credentials_path.map(|path| JsonFile::new(path_with_suffix(path, Self::SUFFIX)));
+        let records = match &file {
+            Some(file) => file.read()?.unwrap_or_default(),
+            None => Default::default(),
+        };
+        Ok(Lockouts {
+            policy,
+            file,
+            records,
+        })
+    }
+
+    pub(super) fn save(&self) -> Result<()> {
+        match &self.file {
+            Some(file) => file.write(&self.records),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails, if login is blocked at the moment
+    pub(super) fn check(&self, login_hash: &str) -> Result<()> {
+        self.check_at(login_hash, now())
+    }
+
+    /// Counts failed attempt. Returns lockout duration, if the login became blocked
+    pub(super) fn register_failure(&mut self, login_hash: &str) -> Option<Duration> {
+        self.register_failure_at(login_hash, now())
+    }
+
+    /// Resets counters after successful login. Returns `true` if there was anything to reset
+    pub(super) fn register_success(&mut self, login_hash: &str) -> bool {
+        self.records.remove(login_hash).is_some()
+    }
+
+    fn check_at(&self, login_hash: &str, now: u64) -> Result<()> {
+        match self.records.get(login_hash) {
+            Some(record) if record.locked_until > now => Err(Error::Policy(format!(
+                "Too many failed login attempts. Try again in {} seconds",
+                record.locked_until - now
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn register_failure_at(&mut self, login_hash: &str, now: u64) -> Option<Duration> {
+        let record = self.records.entry(login_hash.to_string()).or_default();
+        record.failed_attempts += 1;
+        if record.failed_attempts < self.policy.max_attempts {
+            return None;
+        }
+        record.failed_attempts = 0;
+        record.lockouts = record.lockouts.saturating_add(1);
+        let duration = self.policy.lockout_duration(record.lockouts);
+        record.locked_until = now.saturating_add(duration.as_secs());
+        Some(duration)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{LockoutPolicy, Lockouts};
+
+    fn lockouts() -> Lockouts {
+        Lockouts {
+            policy: LockoutPolicy::default(),
+            file: None,
+            records: Default::default(),
+        }
+    }
+
+    #[test]
+    fn lockout_duration_grows_exponentially() {
+        let policy = LockoutPolicy::default();
+        assert_eq!(policy.lockout_duration(1), Duration::from_secs(60));
+        assert_eq!(policy.lockout_duration(2), Duration::from_secs(120));
+        assert_eq!(policy.lockout_duration(3), Duration::from_secs(240));
+        assert_eq!(policy.lockout_duration(7), Duration::from_secs(3600));
+        assert_eq!(policy.lockout_duration(100), Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn login_is_blocked_after_max_attempts() {
+        let mut lockouts = lockouts();
+        assert_eq!(lockouts.register_failure_at("login", 1000), None);
+        assert_eq!(lockouts.register_failure_at("login", 1000), None);
+        assert!(lockouts.check_at("login", 1000).is_ok());
+        assert_eq!(
+            lockouts.register_failure_at("login", 1000),
+            Some(Duration::from_secs(60))
+        );
+        assert!(lockouts.check_at("login", 1000).is_err());
+        assert!(lockouts.check_at("login", 1059).is_err());
+        assert!(lockouts.check_at("login", 1060).is_ok());
+        assert!(lockouts.check_at("other_login", 1000).is_ok());
+
+        for _ in 0..2 {
+            lockouts.register_failure_at("login", 1060);
+        }
+        assert_eq!(
+            lockouts.register_failure_at("login", 1060),
+            Some(Duration::from_secs(120))
+        );
+    }
+
+    #[test]
+    fn success_resets_counters() {
+        let mut lockouts = lockouts();
+        lockouts.register_failure_at("login", 1000);
+        assert!(lockouts.register_success("login"));
+        assert!(!lockouts.register_success("login"));
+        assert_eq!(lockouts.register_failure_at("login", 1000), None);
+    }
+}
@@ -0,0 +1,372 @@
+use super::cli::{LoginArgs, PasswdArgs, Secret};
+use super::config::Config;
+use super::credentials::{CredentialRecord, CredentialsManager};
+use super::crypto::PasswordHash;
+use super::error::{Error, Result};
+use super::lockout::Lockouts;
+use super::registrar::Registrar;
+use super::utils::read_stdin;
+
+pub(super) struct Login;
+
+impl Login {
+    const LOGIN_MESSAGE: &'static str = "Введите логин:";
+    const PASSWORD_MESSAGE: &'static str = "Введите пароль:";
+    const SUCCESS_MESSAGE: &'static str = "Успешный вход";
+    const PASSWORD_CHANGE_MESSAGE: &'static str = "Администратор потребовал сменить пароль";
+    const NEW_PASSWORD_MESSAGE: &'static str = "Введите новый пароль:";
+    const PASSWORD_CHANGED_MESSAGE: &'static str = "Пароль изменён";
+
+    /// Logs the user in and shows the document of their access level.
+    /// Values, missing in `args`, are asked interactively
+    pub(super) fn login(config: &Config, args: &LoginArgs) -> Result<()> {
+        let (login, record) = Self::sign_in(config, args)?;
+        if record.must_change_password {
+            println!("{}", Self::PASSWORD_CHANGE_MESSAGE);
+            Self::change_requested_password(config, &login)?;
+        }
+        println!("{}", Self::SUCCESS_MESSAGE);
+        config.documents.print(&record.access_level)
+    }
+
+    /// Changes password of the user after checking the current one or the reset token,
+    /// if it is issued. Values, missing in `args`, are asked interactively
+    pub(super) fn passwd(config: &Config, args: &PasswdArgs) -> Result<()> {
+        let (login, _) = Self::sign_in(config, &args.user)?;
+        let password = Registrar::read_new_password(
+            &config.password_policy,
+            &login,
+            &args.new_password,
+            Self::NEW_PASSWORD_MESSAGE,
+        )?;
+        let mut credentials = CredentialsManager::open(config)?;
+        Self::set_password(
+            &mut credentials,
+            &login,
+            &password,
+            config.hash_iterations,
+            config.password_policy.password_history,
+        )?;
+        credentials.save()?;
+        println!("{}", Self::PASSWORD_CHANGED_MESSAGE);
+        Ok(())
+    }
+
+    /// Checks login and password, counting failed attempts against lockouts. Returns the login
+    /// and the record of the user. Values, missing in `args`, are asked interactively
+    pub(super) fn sign_in(config: &Config, args: &LoginArgs) -> Result<(String, CredentialRecord)> {
+        let login = match &args.login {
+            Some(login) => login.clone(),
+            None => Self::read_login_data(Self::LOGIN_MESSAGE, "Failed reading user input login")?,
+        };
+        let login_hash = CredentialsManager::login_hash(&login);
+        // Blocked login is refused before asking for the password. The counters are checked
+        // again and changed under the lock of the store, see `CredentialsManager::lockouts`
+        Lockouts::load(config.lockout.clone(), config.store_path())?.check(&login_hash)?;
+
+        let password = args
+            .password
+            .read(Self::PASSWORD_MESSAGE, "Failed reading user input password")?;
+        // Closed or empty input is not a guess, so it is not counted as a failed attempt
+        if password.is_empty() {
+            return Err(Error::Validation("Password is empty".to_string()));
+        }
+
+        let mut credentials = CredentialsManager::open(config)?;
+        let mut lockouts = credentials.lockouts(config.lockout.clone())?;
+        lockouts.check(&login_hash)?;
+        match Self::authenticate(&credentials, &login, &password, config.hash_iterations).cloned() {
+            Some(record) => {
+                if lockouts.register_success(&login_hash) {
+                    lockouts.save()?;
+                }
+                let legacy = record.password.is_legacy() || credentials.has_legacy_key(&login);
+                // Password, which has to be changed, is not worth re-hashing. It may even
+                // be a reset token
+                if legacy && !record.must_change_password {
+                    Self::upgrade_password_hash(
+                        &mut credentials,
+                        &login,
+                        &password,
+                        config.hash_iterations,
+                    )?;
+                    credentials.save()?;
+                } else if legacy || record.login.is_none() {
+                    // Records of older versions learn their login, so it can be listed
+                    credentials.update(&login, record.clone());
+                    credentials.save()?;
+                }
+                Ok((login, record))
+            }
+            None => {
+                let lockout = lockouts.register_failure(&login_hash);
+                lockouts.save()?;
+                match lockout {
+                    Some(duration) => Err(Error::Authentication(format!(
+                        "Wrong login or password. Login is blocked for {} seconds",
+                        duration.as_secs()
+                    ))),
+                    None => Err(Error::Authentication("Wrong login or password".to_string())),
+                }
+            }
+        }
+    }
+
+    /// Asks for a new password, when an administrator requested it. It is read from
+    /// the terminal or stdin even if the current one or the reset token was given in a file
+    fn change_requested_password(config: &Config, login: &str) -> Result<()> {
+        let password = Registrar::read_new_password(
+            &config.password_policy,
+            login,
+            &Secret::Prompt,
+            Self::NEW_PASSWORD_MESSAGE,
+        )?;
+        let mut credentials = CredentialsManager::open(config)?;
+        Self::set_password(
+            &mut credentials,
+            login,
+            &password,
+            config.hash_iterations,
+            config.password_policy.password_history,
+        )?;
+        credentials.save()
+    }
+
+    /// Replaces the password with one, which is neither the current one nor any of
+    /// `history_size` previous ones. The current password goes to the history.
+    /// Clears the request to change the password and the reset token
+    fn set_password(
+        credentials: &mut CredentialsManager,
+        login: &str,
+        password: &str,
+        iterations: u32,
+        history_size: usize,
+    ) -> Result<()> {
+        let old = credentials.get(login).ok_or_else(|| {
+            Error::Authentication("Failed changing password: no such user".to_string())
+        })?;
+        if old.password.verify(password) {
+            return Err(Error::Validation(
+                "New password must differ from the current one".to_string(),
+            ));
+        }
+        if old
+            .password_history
+            .iter()
+            .take(history_size)
+            .any(|previous| previous.verify(password))
+        {
+            return Err(Error::Validation(format!(
+                "New password must differ from the last {} passwords",
+                history_size
+            )));
+        }
+
+        let mut password_history = vec![old.password.clone()];
+        password_history.extend(old.password_history.iter().cloned());
+        password_history.truncate(history_size);
+        let mut record = CredentialRecord::new(
+            PasswordHash::new(password, iterations)?,
+            old.access_level.clone(),
+        );
+        record.password_history = password_history;
+        credentials.update(login, record);
+        Ok(())
+    }
+
+    /// Returns record of the user, if login and password match stored credentials.
+    /// While an administrator's reset token is not used, only the token is accepted,
+    /// so the old password, which may be compromised, can not set the new one.
+    /// Unknown login takes as long to check as a wrong password with `iterations` work factor,
+    /// so response time does not reveal, whether the user exists
+    fn authenticate<'a>(
+        credentials: &'a CredentialsManager,
+        login: &str,
+        password: &str,
+        iterations: u32,
+    ) -> Option<&'a CredentialRecord> {
+        match credentials.get(login) {
+            Some(record) => {
+                let secret = record.reset_token.as_ref().unwrap_or(&record.password);
+                Some(record).filter(|_| secret.verify(password))
+            }
+            None => {
+                PasswordHash::dummy(iterations).verify(password);
+                None
+            }
+        }
+    }
+
+    /// Re-hashes already verified password with the current scheme and moves the record
+    /// under the current login key
+    fn upgrade_password_hash(
+        credentials: &mut CredentialsManager,
+        login: &str,
+        password: &str,
+        iterations: u32,
+    ) -> Result<()> {
+        let mut record = credentials.get(login).cloned().ok_or_else(|| {
+            Error::Authentication("Failed upgrading password hash: no such user".to_string())
+        })?;
+        record.password = PasswordHash::new(password, iterations)?;
+        credentials.update(login, record);
+        Ok(())
+    }
+
+    fn read_login_data(prompt_msg: &str, app_err: &str) -> Result<String> {
+        println!("{}\t", prompt_msg);
+        read_stdin().map_err(|native_err| Error::io(app_err, native_err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::super::cli::{LoginArgs, Secret};
+    use super::super::credentials::testing::{test_config, TempPath};
+    use super::super::credentials::StorageKind;
+    use super::super::crypto::md5_utf8_legacy;
+    use super::super::error::Error;
+    use super::{CredentialRecord, CredentialsManager, Login, PasswordHash};
+
+    fn credentials() -> CredentialsManager {
+        let mut credentials = CredentialsManager::in_memory();
+        credentials.insert(
+            "some_login",
+            CredentialRecord::new(
+                PasswordHash::new("somePa@_ss1", 10).unwrap(),
+                "admin".to_string(),
+            ),
+        );
+        credentials
+    }
+
+    #[test]
+    fn authenticate_valid_credentials() {
+        let credentials = credentials();
+        assert_eq!(
+            Login::authenticate(&credentials, "some_login", "somePa@_ss1", 10)
+                .map(|record| record.access_level.as_str()),
+            Some("admin")
+        );
+    }
+
+    #[test]
+    fn authenticate_invalid_credentials() {
+        let credentials = credentials();
+        assert!(Login::authenticate(&credentials, "some_login", "somePa@_ss2", 10).is_none());
+        assert!(Login::authenticate(&credentials, "other_login", "somePa@_ss1", 10).is_none());
+        assert!(Login::authenticate(&credentials, "somePa@_ss1", "some_login", 10).is_none());
+    }
+
+    #[test]
+    fn empty_password_is_not_counted() {
+        let path = TempPath::new("login-empty-password");
+        let password = TempPath::new("login-empty-password-input");
+        fs::write(&*password, "").unwrap();
+        let config = test_config(StorageKind::Json, &path);
+        let args = LoginArgs {
+            login: Some("some_login".to_string()),
+            password: Secret::File(PathBuf::from(&*password)),
+        };
+
+        for _ in 0..=config.lockout.max_attempts {
+            assert!(matches!(
+                Login::sign_in(&config, &args),
+                Err(Error::Validation(_))
+            ));
+        }
+        let credentials = CredentialsManager::open(&config).unwrap();
+        let lockouts = credentials.lockouts(config.lockout.clone()).unwrap();
+        assert!(lockouts
+            .check(&CredentialsManager::login_hash("some_login"))
+            .is_ok());
+    }
+
+    #[test]
+    fn upgrade_legacy_password_hash() {
+        let mut credentials = CredentialsManager::in_memory();
+        let legacy = PasswordHash::Md5 {
+            hash: md5_utf8_legacy("somePa@_ss1"),
+        };
+        credentials.insert(
+            "some_login",
+            CredentialRecord::new(legacy, "user".to_string()),
+        );
+        assert!(Login::authenticate(&credentials, "some_login", "somePa@_ss1", 10).is_some());
+
+        Login::upgrade_password_hash(&mut credentials, "some_login", "somePa@_ss1", 10).unwrap();
+        let record = Login::authenticate(&credentials, "some_login", "somePa@_ss1", 10).unwrap();
+        assert!(!record.password.is_legacy());
+        assert_eq!(record.access_level, "user");
+        assert!(matches!(
+            Login::upgrade_password_hash(&mut credentials, "other_login", "x", 10),
+            Err(Error::Authentication(_))
+        ));
+    }
+
+    #[test]
+    fn authenticate_with_reset_token() {
+        let mut credentials = credentials();
+        let mut record = credentials.get("some_login").unwrap().clone();
+        record.must_change_password = true;
+        record.reset_token = Some(PasswordHash::new("token", 10).unwrap());
+        credentials.update("some_login", record);
+
+        assert!(Login::authenticate(&credentials, "some_login", "token", 10).is_some());
+        assert!(Login::authenticate(&credentials, "some_login", "somePa@_ss1", 10).is_none());
+        assert!(Login::authenticate(&credentials, "some_login", "other_token", 10).is_none());
+        assert!(Login::authenticate(&credentials, "other_login", "token", 10).is_none());
+
+        Login::set_password(&mut credentials, "some_login", "otherPa@_ss2", 10, 5).unwrap();
+        assert!(Login::authenticate(&credentials, "some_login", "token", 10).is_none());
+        assert!(Login::authenticate(&credentials, "some_login", "otherPa@_ss2", 10).is_some());
+    }
+
+    #[test]
+    fn refuse_recent_passwords() {
+        let mut credentials = credentials();
+        for password in ["firstPa@_ss2", "secondPa@_ss3", "thirdPa@_ss4"] {
+            Login::set_password(&mut credentials, "some_login", password, 10, 2).unwrap();
+        }
+        let record = credentials.get("some_login").unwrap();
+        assert_eq!(record.password_history.len(), 2);
+        assert!(record.password_history[0].verify("secondPa@_ss3"));
+
+        for password in ["thirdPa@_ss4", "secondPa@_ss3", "firstPa@_ss2"] {
+            assert!(
+                Login::set_password(&mut credentials, "some_login", password, 10, 2).is_err(),
+                "{}",
+                password
+            );
+        }
+        // Without history only the current password is refused
+        assert!(Login::set_password(&mut credentials, "some_login", "firstPa@_ss2", 10, 0).is_ok());
+        assert!(credentials
+            .get("some_login")
+            .unwrap()
+            .password_history
+            .is_empty());
+        assert!(Login::set_password(&mut credentials, "some_login", "somePa@_ss1", 10, 0).is_ok());
+    }
+
+    #[test]
+    fn set_requested_password() {
+        let mut credentials = credentials();
+        let mut record = credentials.get("some_login").unwrap().clone();
+        record.must_change_password = true;
+        credentials.update("some_login", record);
+
+        assert!(Login::set_password(&mut credentials, "some_login", "somePa@_ss1", 10, 5).is_err());
+        Login::set_password(&mut credentials, "some_login", "otherPa@_ss2", 10, 5).unwrap();
+        let record = Login::authenticate(&credentials, "some_login", "otherPa@_ss2", 10).unwrap();
+        assert!(!record.must_change_password);
+        assert_eq!(record.access_level, "admin");
+        assert!(matches!(
+            Login::set_password(&mut credentials, "other_login", "otherPa@_ss2", 10, 5),
+            Err(Error::Authentication(_))
+        ));
+    }
+}
@@ -0,0 +1,515 @@
+mod blocklist;
+mod strength;
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::crypto::normalize_password;
+
+use super::error::{Error, Result};
+
+use blocklist::Blocklist;
+pub(crate) use strength::Strength;
+
+/// Single requirement of the password policy. Requirements, which a password fails,
+/// are reported as violations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Rule {
+    /// Password has at least that many symbols (grapheme clusters)
+    MinLength(usize),
+    Uppercase,
+    Lowercase,
+    Digit,
+    /// Password has at least one of the policy special characters
+    SpecialCharacter,
+    NoWhitespace,
+    /// Password differs from the login
+    NotEqualToLogin,
+    /// Estimated strength of the password is at least that score
+    MinStrength(u8),
+    /// Password is not in the list of common passwords
+    NotBlocklisted,
+}
+
+impl Rule {
+    /// Description of the requirement for the checklist shown to the user
+    pub(crate) fn description(&self) -> String {
+        match self {
+            Rule::MinLength(min_length) => format!("Не менее {} символов", min_length),
+            Rule::Uppercase => "Заглавная буква".to_string(),
+            Rule::Lowercase => "Строчная буква".to_string(),
+            Rule::Digit => "Цифра".to_string(),
+            Rule::SpecialCharacter => "Специальный символ".to_string(),
+            Rule::NoWhitespace => "Без пробелов".to_string(),
+            Rule::NotEqualToLogin => "Не совпадает с логином".to_string(),
+            Rule::MinStrength(score) => {
+                format!("Надёжность не ниже {} из {}", score, Strength::MAX_SCORE)
+            }
+            Rule::NotBlocklisted => "Не входит в список распространённых паролей".to_string(),
+        }
+    }
+}
+
+/// Message of the violated rule
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::MinLength(min_length) => {
+                write!(f, "Password length is less than {} symbols", min_length)
+            }
+            Rule::Uppercase => write!(f, "Password has no upper case letters"),
+            Rule::Lowercase => write!(f, "Password has no lower case letters"),
+            Rule::Digit => write!(f, "Password has no numbers"),
+            Rule::SpecialCharacter => write!(f, "Password has no special symbols"),
+            Rule::NoWhitespace => write!(f, "Password has spaces"),
+            Rule::NotEqualToLogin => write!(f, "Login is equal to password"),
+            Rule::MinStrength(score) => write!(
+                f,
+                "Password strength is less than {} of {}",
+                score,
+                Strength::MAX_SCORE
+            ),
+            Rule::NotBlocklisted => write!(f, "Password is too common"),
+        }
+    }
+}
+
+/// Requirements to new passwords. Fields, missing in the policy file, keep default values
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct PasswordPolicy {
+    min_length: usize,
+    require_uppercase: bool,
+    require_lowercase: bool,
+    require_digit: bool,
+    require_special_character: bool,
+    /// Symbols, which are counted as special characters
+    special_characters: String,
+    /// Least score of estimated strength, 0 turns the check off
+    min_strength: u8,
+    /// Common passwords, which are refused
+    blocklist: Option<Blocklist>,
+    /// Number of previous passwords, which can not be reused besides the current one
+    pub(crate) password_history: usize,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: 8,
+            require_uppercase: true,
+            require_lowercase: true,
+            require_digit: true,
+            require_special_character: true,
+            special_characters: "!№@#$%^&*():;[]?-_=+{},.\"".to_string(),
+            min_strength: 0,
+            blocklist: None,
+            password_history: 5,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Upper bound of `password_history`, as each remembered password takes
+    /// as long to check as a login
+    const MAX_PASSWORD_HISTORY: usize = 24;
+
+    /// Reads policy from a JSON file and validates it
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            Error::io(
+                format!("Failed reading password policy {}", path.display()),
+                e,
+            )
+        })?;
+        let mut policy: Self = serde_json::from_str(&contents).map_err(|e| {
+            Error::Config(format!("Invalid password policy {}: {}", path.display(), e))
+        })?;
+        policy.validate().map_err(|e| {
+            Error::Config(format!("Invalid password policy {}: {}", path.display(), e))
+        })?;
+        if let Some(blocklist) = &mut policy.blocklist {
+            blocklist.load(path.parent().unwrap_or_else(|| Path::new("")))?;
+        }
+        Ok(policy)
+    }
+
+    /// Checks, that the policy can be satisfied and does not contradict itself
+    fn validate(&self) -> std::result::Result<(), String> {
+        if self.min_length == 0 {
+            return Err("min_length must be positive".to_string());
+        }
+        if self.require_special_character && self.special_characters.is_empty() {
+            return Err("special_characters must not be empty, \
+                        if special characters are required"
+                .to_string());
+        }
+        if self.password_history > Self::MAX_PASSWORD_HISTORY {
+            return Err(format!(
+                "password_history must not be greater than {}",
+                Self::MAX_PASSWORD_HISTORY
+            ));
+        }
+        if self.min_strength > Strength::MAX_SCORE {
+            return Err(format!(
+                "min_strength must not be greater than {}",
+                Strength::MAX_SCORE
+            ));
+        }
+        if let Some(c) = self
+            .special_characters
+            .chars()
+            .find(|c| c.is_alphanumeric() || c.is_whitespace())
+        {
+            return Err(format!(
+                "special_characters must not contain letters, digits or spaces, found {:?}",
+                c
+            ));
+        }
+        Ok(())
+    }
+
+    /// Rules, enabled by the policy
+    pub(crate) fn rules(&self) -> Vec<Rule> {
+        let optional = vec![
+            (self.require_uppercase, Rule::Uppercase),
+            (self.require_lowercase, Rule::Lowercase),
+            (self.require_digit, Rule::Digit),
+            (self.require_special_character, Rule::SpecialCharacter),
+        ];
+        let mut rules = vec![Rule::MinLength(self.min_length)];
+        rules.extend(
+            optional
+                .into_iter()
+                .filter(|(enabled, _)| *enabled)
+                .map(|(_, rule)| rule),
+        );
+        rules.extend([Rule::NoWhitespace, Rule::NotEqualToLogin]);
+        if self.min_strength > 0 {
+            rules.push(Rule::MinStrength(self.min_strength));
+        }
+        if self.blocklist.is_some() {
+            rules.push(Rule::NotBlocklisted);
+        }
+        rules
+    }
+
+    /// Checks all rules and returns each of them together with its result.
+    /// Rules are checked on the normalized password, which is actually hashed
+    pub(crate) fn checklist(&self, login: &str, password: &str) -> Vec<(Rule, bool)> {
+        let normalized_login = normalize_password(login);
+        let normalized = normalize_password(password);
+        self.rules()
+            .into_iter()
+            .map(|rule| {
+                let satisfied = match rule {
+                    // Normalization turns some special characters into letters, e.g. "№" into "No"
+                    Rule::SpecialCharacter => self.is_satisfied(&rule, login, password),
+                    _ => self.is_satisfied(&rule, &normalized_login, &normalized),
+                };
+                (rule, satisfied)
+            })
+            .collect()
+    }
+
+    /// Rules, which the password violates
+    pub(crate) fn violations(&self, login: &str, password: &str) -> Vec<Rule> {
+        self.checklist(login, password)
+            .into_iter()
+            .filter(|(_, satisfied)| !satisfied)
+            .map(|(rule, _)| rule)
+            .collect()
+    }
+
+    fn is_satisfied(&self, rule: &Rule, login: &str, password: &str) -> bool {
+        match rule {
+            Rule::MinLength(min_length) => password.graphemes(true).count() >= *min_length,
+            Rule::Uppercase => password.chars().any(char::is_uppercase),
+            Rule::Lowercase => password.chars().any(char::is_lowercase),
+            Rule::Digit => password.chars().any(|c| c.is_ascii_digit()),
+            Rule::SpecialCharacter => password
+                .chars()
+                .any(|c| self.special_characters.contains(c)),
+            Rule::NoWhitespace => !password.chars().any(char::is_whitespace),
+            Rule::NotEqualToLogin => password != login,
+            Rule::MinStrength(score) => Strength::estimate(login, password).score >= *score,
+            Rule::NotBlocklisted => !self
+                .blocklist
+                .as_ref()
+                .is_some_and(|blocklist| blocklist.contains(password)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::super::credentials::testing::TempPath;
+    use super::{PasswordPolicy, Rule};
+
+    #[test]
+    fn check_password_length() {
+        let policy = PasswordPolicy::default();
+        let password = "some_pa\n";
+        assert_eq!(password.len(), 8);
+        assert!(!policy
+            .violations("login", password)
+            .contains(&Rule::MinLength(8)));
+        assert!(policy
+            .violations("login", password.trim())
+            .contains(&Rule::MinLength(8)));
+    }
+
+    #[test]
+    fn length_is_counted_in_symbols() {
+        let policy = PasswordPolicy::default();
+        // 8 bytes, but 4 symbols
+        assert!(policy
+            .violations("login", "Пр1!")
+            .contains(&Rule::MinLength(8)));
+        assert!(policy.violations("login", "Пароль1!").is_empty());
+        // "е" with combining diaeresis is a single symbol
+        assert!(policy.violations("login", "Пароль\u{308}1!").is_empty());
+        assert!(policy
+            .violations("login", "Паро\u{308}1!")
+            .contains(&Rule::MinLength(8)));
+    }
+
+    #[test]
+    fn unicode_letters_satisfy_case_rules() {
+        let policy = PasswordPolicy::default();
+        assert!(policy
+            .violations("login", "пароль_1!")
+            .contains(&Rule::Uppercase));
+        assert!(policy
+            .violations("login", "ПАРОЛЬ_1!")
+            .contains(&Rule::Lowercase));
+        assert!(policy.violations("login", "Ünïcödé_1").is_empty());
+        // Full-width digit is normalized to the ASCII one
+        assert!(policy.violations("login", "Пароль_\u{ff11}").is_empty());
+        // Login is compared after normalization as well
+        assert!(policy
+            .violations("Пароль_\u{ff11}", "Пароль_1")
+            .contains(&Rule::NotEqualToLogin));
+    }
+
+    #[test]
+    fn check_password_is_valid() {
+        let policy = PasswordPolicy::default();
+        let password_valid = "somePa@_ss1";
+        let invalid_passwords = [
+            "somepass",
+            "SOMEPASS",
+            "Somepass",
+            "somePass1",
+            "somePa@ ss1",
+        ];
+
+        assert!(policy.violations("login", password_valid).is_empty());
+
+        for invalid_pass in invalid_passwords {
+            assert!(!policy.violations("login", invalid_pass).is_empty());
+        }
+    }
+
+    #[test]
+    fn all_violations_are_reported() {
+        let policy = PasswordPolicy::default();
+        assert_eq!(
+            policy.violations("login", "pass word"),
+            vec![
+                Rule::Uppercase,
+                Rule::Digit,
+                Rule::SpecialCharacter,
+                Rule::NoWhitespace
+            ]
+        );
+        assert_eq!(
+            policy.violations("", ""),
+            vec![
+                Rule::MinLength(8),
+                Rule::Uppercase,
+                Rule::Lowercase,
+                Rule::Digit,
+                Rule::SpecialCharacter,
+                Rule::NotEqualToLogin
+            ]
+        );
+        assert_eq!(
+            policy.violations("somePa@_ss1", "somePa@_ss1"),
+            vec![Rule::NotEqualToLogin]
+        );
+    }
+
+    #[test]
+    fn each_rule_is_checked() {
+        let policy = PasswordPolicy::default();
+        let cases = [
+            ("somePa@_s1", None),
+            ("somePa@1", None),
+            ("somPa@1", Some(Rule::MinLength(8))),
+            ("somepa@_ss1", Some(Rule::Uppercase)),
+            ("SOMEPA@_SS1", Some(Rule::Lowercase)),
+            ("somePa@_ss", Some(Rule::Digit)),
+            ("somePass11", Some(Rule::SpecialCharacter)),
+            ("somePa@\tss1", Some(Rule::NoWhitespace)),
+        ];
+        for (password, violation) in cases {
+            assert_eq!(
+                policy.violations("login", password),
+                violation.into_iter().collect::<Vec<_>>(),
+                "{:?}",
+                password
+            );
+        }
+    }
+
+    #[test]
+    fn each_special_symbol_is_accepted() {
+        let policy = PasswordPolicy::default();
+        let special_symbols = [
+            "@", r#"""#, "#", "№", r#"$"#, ";", "%", "^", ":", ";", "&", "?", "*", "(", ")", "_",
+            "-", "=", "+", "{", "}", ".", ",", "!", "[", "]",
+        ];
+        for symbol in special_symbols {
+            let password = format!("somePass1{}", symbol);
+            assert!(
+                policy.violations("login", &password).is_empty(),
+                "{:?}",
+                password
+            );
+        }
+    }
+
+    #[test]
+    fn checklist_has_every_rule() {
+        let policy = PasswordPolicy::default();
+        let checklist = policy.checklist("login", "somepass");
+        assert_eq!(checklist.len(), policy.rules().len());
+        assert!(checklist.contains(&(Rule::MinLength(8), true)));
+        assert!(checklist.contains(&(Rule::Uppercase, false)));
+    }
+
+    fn parse(json: &str) -> PasswordPolicy {
+        let policy: PasswordPolicy = serde_json::from_str(json).unwrap();
+        policy.validate().unwrap();
+        policy
+    }
+
+    #[test]
+    fn missing_fields_keep_defaults() {
+        assert_eq!(parse("{}"), PasswordPolicy::default());
+
+        let policy = parse(r#"{"min_length": 12, "special_characters": "!?"}"#);
+        assert_eq!(
+            policy.violations("login", "somePa@_ss1"),
+            vec![Rule::MinLength(12), Rule::SpecialCharacter]
+        );
+        assert!(policy.violations("login", "somePa?_ss123").is_empty());
+    }
+
+    #[test]
+    fn disabled_rules_are_not_checked() {
+        let policy = parse(
+            r#"{"require_uppercase": false, "require_digit": false,
+                "require_special_character": false, "special_characters": ""}"#,
+        );
+        assert_eq!(
+            policy.rules(),
+            vec![
+                Rule::MinLength(8),
+                Rule::Lowercase,
+                Rule::NoWhitespace,
+                Rule::NotEqualToLogin
+            ]
+        );
+        assert!(policy.violations("login", "somepass").is_empty());
+    }
+
+    #[test]
+    fn invalid_policy_is_rejected() {
+        let invalid = [
+            r#"{"min_length": 0}"#,
+            r#"{"min_length": -1}"#,
+            r#"{"special_characters": ""}"#,
+            r#"{"special_characters": "!a"}"#,
+            r#"{"special_characters": "! "}"#,
+            r#"{"min_strength": 5}"#,
+            r#"{"password_history": 25}"#,
+            r#"{"min_lenght": 10}"#,
+            r#"{"require_digit": "yes"}"#,
+        ];
+        let path = TempPath::new("policy-invalid");
+        for json in invalid {
+            fs::write(&*path, json).unwrap();
+            assert!(PasswordPolicy::load(&path).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn min_strength_is_checked() {
+        let policy = parse(r#"{"min_strength": 3}"#);
+        assert_eq!(
+            policy.rules()[policy.rules().len() - 1],
+            Rule::MinStrength(3)
+        );
+        assert_eq!(
+            policy.violations("login", "Qwerty_123"),
+            vec![Rule::MinStrength(3)]
+        );
+        assert!(policy.violations("login", "x7#kP!q2mQ").is_empty());
+        assert!(!PasswordPolicy::default()
+            .rules()
+            .iter()
+            .any(|rule| matches!(rule, Rule::MinStrength(_))));
+    }
+
+    #[test]
+    fn blocklisted_password_is_refused() {
+        let blocklist = TempPath::new("policy-blocklist");
+        let path = TempPath::new("policy-with-blocklist");
+        fs::write(&*blocklist, "password1!\nqwerty123!\n").unwrap();
+        fs::write(
+            &*path,
+            format!(
+                r#"{{"blocklist": {{"path": "{}", "leetspeak": true}}}}"#,
+                blocklist.file_name().unwrap().to_string_lossy()
+            ),
+        )
+        .unwrap();
+
+        let policy = PasswordPolicy::load(&path).unwrap();
+        assert_eq!(policy.rules().last(), Some(&Rule::NotBlocklisted));
+        assert_eq!(
+            policy.violations("login", "Password1!"),
+            vec![Rule::NotBlocklisted]
+        );
+        assert_eq!(
+            policy.violations("login", "P@ssw0rd1!"),
+            vec![Rule::NotBlocklisted]
+        );
+        assert!(policy.violations("login", "somePa@_ss1").is_empty());
+        assert!(!PasswordPolicy::default()
+            .rules()
+            .contains(&Rule::NotBlocklisted));
+
+        drop(blocklist);
+        assert!(PasswordPolicy::load(&path).is_err());
+    }
+
+    #[test]
+    fn load_policy_file() {
+        let path = TempPath::new("policy");
+        assert!(PasswordPolicy::load(&path).is_err());
+
+        fs::write(&*path, r#"{"min_length": 10, "require_digit": false}"#).unwrap();
+        let policy = PasswordPolicy::load(&path).unwrap();
+        assert_eq!(policy.min_length, 10);
+        assert!(!policy.rules().contains(&Rule::Digit));
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use super::super::crypto::{normalize_password, to_hex};
+use super::super::error::{Error, Result};
+
+/// List of common and breached passwords, which are refused on registration.
+///
+/// File has one password per line. Passwords are matched case-insensitively after
+/// normalization, hashed lists keep lowercase hex SHA-256 of such passwords instead
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Blocklist {
+    /// File of the list. Relative path is resolved against directory of the policy file
+    path: PathBuf,
+    /// Whether lines of the file are SHA-256 digests rather than passwords
+    #[serde(default)]
+    hashed: bool,
+    /// Whether digits and symbols, used in place of letters ("p@ssw0rd"), are
+    /// replaced back before matching
+    #[serde(default)]
+    leetspeak: bool,
+    #[serde(skip)]
+    entries: HashSet<String>,
+}
+
+impl Blocklist {
+    /// Reads the list file into memory
+    pub(super) fn load(&mut self, base_dir: &Path) -> Result<()> {
+        self.path = base_dir.join(&self.path);
+        let contents = fs::read_to_string(&self.path).map_err(|e| {
+            Error::io(
+                format!("Failed reading password blocklist {}", self.path.display()),
+                e,
+            )
+        })?;
+        self.entries = self.parse(&contents)?;
+        Ok(())
+    }
+
+    fn parse(&self, contents: &str) -> Result<HashSet<String>> {
+        let mut entries = HashSet::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if self.hashed {
+                let digest = line.to_ascii_lowercase();
+                if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(Error::Config(format!(
+                        "Invalid password blocklist {}: line {} is not a SHA-256 digest",
+                        self.path.display(),
+                        i + 1
+                    )));
+                }
+                entries.insert(digest);
+            } else {
+                entries.extend(self.keys(line));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Whether the password is in the list
+    pub(super) fn contains(&self, password: &str) -> bool {
+        self.keys(password)
+            .into_iter()
+            .any(|key| self.entries.contains(&key))
+    }
+
+    /// Forms of the password, which are looked up in the list. With leetspeak these are
+    /// the password with all substitutions replaced back, which matches entries of a plain
+    /// list processed the same way, and with only substitutions between letters replaced,
+    /// which matches "p@ssw0rd1" against "password1" in a hashed list as well
+    fn keys(&self, password: &str) -> Vec<String> {
+        let password = normalize_password(password).to_lowercase();
+        let mut forms = vec![password];
+        if self.leetspeak {
+            for between_letters in [false, true] {
+                let unleeted = Self::replace_leetspeak(&forms[0], between_letters);
+                if !forms.contains(&unleeted) {
+                    forms.push(unleeted);
+                }
+            }
+        }
+        if self.hashed {
+            forms
+                .iter()
+                .map(|form| to_hex(&Sha256::digest(form.as_bytes())))
+                .collect()
+        } else {
+            forms
+        }
+    }
+
+    fn replace_leetspeak(password: &str, between_letters: bool) -> String {
+        let chars: Vec<char> = password.chars().collect();
+        let is_letter = |i: Option<usize>| {
+            i.and_then(|i| chars.get(i))
+                .is_some_and(|c| c.is_alphabetic())
+        };
+        chars
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| {
+                if between_letters && !(is_letter(i.checked_sub(1)) && is_letter(Some(i + 1))) {
+                    return c;
+                }
+                match c {
+                    '0' => 'o',
+                    '1' | '!' => 'i',
+                    '3' => 'e',
+                    '4' | '@' => 'a',
+                    '5' | '$' => 's',
+                    '7' => 't',
+                    c => c,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::super::super::credentials::testing::TempPath;
+    use super::Blocklist;
+
+    fn blocklist(contents: &str, hashed: bool, leetspeak: bool) -> Blocklist {
+        let mut blocklist = Blocklist {
+            path: PathBuf::from("blocklist.txt"),
+            hashed,
+            leetspeak,
+            entries: Default::default(),
+        };
+        blocklist.entries = blocklist.parse(contents).unwrap();
+        blocklist
+    }
+
+    #[test]
+    fn match_case_insensitively() {
+        let blocklist = blocklist("password1!\n\nqwerty\r\n", false, false);
+        assert!(blocklist.contains("Password1!"));
+        assert!(blocklist.contains("QWERTY"));
+        assert!(!blocklist.contains("P@ssw0rd1!"));
+        assert!(!blocklist.contains("somePa@_ss1"));
+    }
+
+    #[test]
+    fn match_leetspeak() {
+        let blocklist = blocklist("password1!\nqwerty", false, true);
+        assert!(blocklist.contains("P@ssw0rd1!"));
+        assert!(blocklist.contains("Password1!"));
+        assert!(blocklist.contains("QW3R7Y"));
+        assert!(blocklist.contains("qw3rty"));
+        assert!(!blocklist.contains("somePa@_ss1"));
+    }
+
+    #[test]
+    fn match_hashes() {
+        // SHA-256 of "password1!"
+        let contents = "\
+            65C21921CA10A8502757EFC9AA552874D181C6206FEB2845A921EB57F5E518D4\n\
+            not a digest";
+        assert!(blocklist("", true, false).parse(contents).is_err());
+
+        let contents = contents.lines().next().unwrap();
+        let blocklist = blocklist(contents, true, true);
+        assert!(blocklist.contains("Password1!"));
+        assert!(blocklist.contains("P@ssw0rd1!"));
+        assert!(!blocklist.contains("somePa@_ss1"));
+    }
+
+    #[test]
+    fn load_relative_to_policy() {
+        let path = TempPath::new("blocklist");
+        fs::write(&*path, "password1!\n").unwrap();
+        let dir = path.parent().unwrap();
+
+        let mut blocklist = blocklist("", false, false);
+        blocklist.path = PathBuf::from(path.file_name().unwrap());
+        blocklist.load(dir).unwrap();
+        assert!(blocklist.contains("Password1!"));
+
+        let dir = dir.to_path_buf();
+        drop(path);
+        assert!(blocklist.load(&dir).is_err());
+    }
+}
@@ -0,0 +1,343 @@
+use std::ops::Range;
+
+use super::super::crypto::normalize_password;
+
+/// Kind of predictable part of a password
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PatternKind {
+    /// Consecutive letters or digits: "abc", "4321"
+    Sequence,
+    /// Repeated symbol or group of symbols: "aaa", "abab"
+    Repeat,
+    /// Adjacent keys of a keyboard row: "qwer", "фыва"
+    KeyboardWalk,
+    /// Login of the user, possibly reversed
+    Login,
+}
+
+/// Predictable part of a password
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Pattern {
+    pub(crate) kind: PatternKind,
+    /// Matched part of the lowercase password
+    pub(crate) text: String,
+}
+
+impl Pattern {
+    /// Description for the user
+    pub(crate) fn description(&self) -> String {
+        match self.kind {
+            PatternKind::Sequence => format!("последовательность \"{}\"", self.text),
+            PatternKind::Repeat => format!("повтор \"{}\"", self.text),
+            PatternKind::KeyboardWalk => format!("клавиатурный ряд \"{}\"", self.text),
+            PatternKind::Login => "логин в пароле".to_string(),
+        }
+    }
+
+    /// Bits, which guessing this part takes, if a symbol takes `symbol_bits`
+    fn entropy(&self, symbol_bits: f64) -> f64 {
+        match self.kind {
+            PatternKind::Login => 1.0,
+            _ => symbol_bits + (self.text.chars().count() as f64).log2(),
+        }
+    }
+}
+
+/// Estimated strength of a password
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Strength {
+    /// Estimated entropy in bits
+    pub(crate) entropy: f64,
+    pub(crate) patterns: Vec<Pattern>,
+    /// From 0 (guessed instantly) to `MAX_SCORE`
+    pub(crate) score: u8,
+}
+
+impl Strength {
+    pub(crate) const MAX_SCORE: u8 = 4;
+    /// Least entropy in bits for scores from 1 to `MAX_SCORE`
+    const SCORE_THRESHOLDS: [f64; 4] = [25.0, 40.0, 60.0, 80.0];
+    /// Shortest keyboard walk, which is reported. Shorter ones are too common by chance
+    const MIN_WALK_LEN: usize = 4;
+    const MIN_SEQUENCE_LEN: usize = 3;
+    const MIN_LOGIN_LEN: usize = 3;
+    const KEYBOARD_ROWS: [&'static str; 6] = [
+        "qwertyuiop",
+        "asdfghjkl",
+        "zxcvbnm",
+        "йцукенгшщзхъ",
+        "фывапролджэ",
+        "ячсмитьбю",
+    ];
+
+    /// Estimates entropy of the password as if it was guessed symbol by symbol from
+    /// the classes it uses, except detected patterns, which are guessed as a whole
+    pub(crate) fn estimate(login: &str, password: &str) -> Self {
+        let normalized = normalize_password(password);
+        let password: Vec<char> = normalized.to_lowercase().chars().collect();
+        let login: Vec<char> = normalize_password(login).to_lowercase().chars().collect();
+
+        let mut covered = vec![false; password.len()];
+        let mut patterns = Vec::new();
+        let mut add = |kind, range: Range<usize>| {
+            if covered[range.clone()].iter().any(|c| *c) {
+                return;
+            }
+            covered[range.clone()].iter_mut().for_each(|c| *c = true);
+            patterns.push(Pattern {
+                kind,
+                text: password[range].iter().collect(),
+            });
+        };
+        for range in Self::find_login(&password, &login) {
+            add(PatternKind::Login, range);
+        }
+        for range in Self::find_keyboard_walks(&password) {
+            add(PatternKind::KeyboardWalk, range);
+        }
+        for range in Self::find_sequences(&password) {
+            add(PatternKind::Sequence, range);
+        }
+        for range in Self::find_repeats(&password) {
+            add(PatternKind::Repeat, range);
+        }
+
+        let symbol_bits = Self::symbol_bits(&normalized);
+        let entropy = covered.iter().filter(|c| !**c).count() as f64 * symbol_bits
+            + patterns.iter().map(|p| p.entropy(symbol_bits)).sum::<f64>();
+        let score = Self::SCORE_THRESHOLDS
+            .iter()
+            .filter(|threshold| entropy >= **threshold)
+            .count() as u8;
+        Strength {
+            entropy,
+            patterns,
+            score,
+        }
+    }
+
+    /// Bits per symbol, chosen from all classes, which the password uses
+    fn symbol_bits(password: &str) -> f64 {
+        let uses = |is_class: fn(&char) -> bool| password.chars().any(|c| is_class(&c));
+        let classes = [
+            (uses(char::is_ascii_lowercase), 26),
+            (uses(char::is_ascii_uppercase), 26),
+            (uses(char::is_ascii_digit), 10),
+            (uses(char::is_ascii_punctuation), 33),
+            (uses(|c| !c.is_ascii()), 66),
+        ];
+        let alphabet: u32 = classes
+            .iter()
+            .filter(|(used, _)| *used)
+            .map(|(_, size)| size)
+            .sum();
+        (alphabet.max(1) as f64).log2()
+    }
+
+    fn find_login(password: &[char], login: &[char]) -> Vec<Range<usize>> {
+        if login.len() < Self::MIN_LOGIN_LEN {
+            return Vec::new();
+        }
+        let reversed: Vec<char> = login.iter().rev().cloned().collect();
+        (0..password.len())
+            .filter(|&i| password[i..].starts_with(login) || password[i..].starts_with(&reversed))
+            .map(|i| i..i + login.len())
+            .collect()
+    }
+
+    fn find_keyboard_walks(password: &[char]) -> Vec<Range<usize>> {
+        let rows: Vec<Vec<char>> = Self::KEYBOARD_ROWS
+            .iter()
+            .flat_map(|row| vec![row.chars().collect(), row.chars().rev().collect()])
+            .collect();
+        let in_row = |part: &[char]| {
+            rows.iter()
+                .any(|row| row.windows(part.len()).any(|w| w == part))
+        };
+        Self::find_runs(password, Self::MIN_WALK_LEN, in_row)
+    }
+
+    fn find_sequences(password: &[char]) -> Vec<Range<usize>> {
+        let continues_sequence = |part: &[char]| {
+            let last = part.len() - 1;
+            let step = |i: usize| part[i] as i64 - part[i - 1] as i64;
+            part[last].is_alphanumeric()
+                && (last == 0 || (step(1).abs() == 1 && step(last) == step(1)))
+        };
+        Self::find_runs(password, Self::MIN_SEQUENCE_LEN, continues_sequence)
+    }
+
+    /// Non-overlapping longest parts, at least `min_len` long, found in a single pass.
+    /// `extends(part)` tells, whether the part matches, given that it matches without
+    /// its last symbol, so each part is grown a symbol at a time
+    fn find_runs(
+        password: &[char],
+        min_len: usize,
+        extends: impl Fn(&[char]) -> bool,
+    ) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        let mut start = 0;
+        while start < password.len() {
+            let mut end = start;
+            while end < password.len() && extends(&password[start..=end]) {
+                end += 1;
+            }
+            if end - start >= min_len {
+                ranges.push(start..end);
+                start = end;
+            } else {
+                start += 1;
+            }
+        }
+        ranges
+    }
+
+    /// Non-overlapping longest parts, which are a symbol, repeated three or more times,
+    /// or a group of symbols, repeated twice or more
+    fn find_repeats(password: &[char]) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        let mut start = 0;
+        while start < password.len() {
+            match Self::longest_repeat(&password[start..]) {
+                Some(len) => {
+                    ranges.push(start..start + len);
+                    start += len;
+                }
+                None => start += 1,
+            }
+        }
+        ranges
+    }
+
+    /// Length of the longest repeat at the start of `text`. Matches of the text with its
+    /// suffixes are found as by Z-algorithm, so the text is scanned once. Part of `len`
+    /// symbols repeats the group of `unit` symbols, if the text matches its suffix from
+    /// `unit` for `len - unit` symbols
+    fn longest_repeat(text: &[char]) -> Option<usize> {
+        let units = text.len() / 2 + 1;
+        let mut matches = vec![0; units];
+        let mut longest = None;
+        // Rightmost match found so far, as `left..right` range of the text
+        let (mut left, mut right) = (0, 0);
+        for unit in 1..units {
+            let mut len = if unit < right {
+                (right - unit).min(matches[unit - left])
+            } else {
+                0
+            };
+            while unit + len < text.len() && text[len] == text[unit + len] {
+                len += 1;
+            }
+            matches[unit] = len;
+            if unit + len > right {
+                left = unit;
+                right = unit + len;
+            }
+            let repeat = (unit + len) / unit * unit;
+            if repeat >= 2 * unit && repeat >= 3 && longest < Some(repeat) {
+                longest = Some(repeat);
+            }
+        }
+        longest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Pattern, PatternKind, Strength};
+
+    fn patterns(login: &str, password: &str) -> Vec<(PatternKind, String)> {
+        Strength::estimate(login, password)
+            .patterns
+            .into_iter()
+            .map(|Pattern { kind, text }| (kind, text))
+            .collect()
+    }
+
+    #[test]
+    fn detect_patterns() {
+        use PatternKind::*;
+
+        assert_eq!(
+            patterns("login", "abcd!XyZ9876"),
+            vec![
+                (Sequence, "abcd".to_string()),
+                (Sequence, "xyz".to_string()),
+                (Sequence, "9876".to_string())
+            ]
+        );
+        assert_eq!(
+            patterns("login", "Qwerty!ФЫВА"),
+            vec![
+                (KeyboardWalk, "qwerty".to_string()),
+                (KeyboardWalk, "фыва".to_string())
+            ]
+        );
+        assert_eq!(
+            patterns("login", "aaa_1212_x"),
+            vec![(Repeat, "aaa".to_string()), (Repeat, "1212".to_string())]
+        );
+        assert_eq!(
+            patterns("Admin", "my_ADMIN_nimda"),
+            vec![(Login, "admin".to_string()), (Login, "nimda".to_string())]
+        );
+        assert!(patterns("ab", "x7#kP!ab2mQ").is_empty());
+    }
+
+    #[test]
+    fn patterns_lower_entropy() {
+        let random = Strength::estimate("login", "x7#kP!q2mQ");
+        let walk = Strength::estimate("login", "qwertY!123");
+        assert!(random.patterns.is_empty());
+        assert!(walk.entropy < random.entropy);
+        assert!(walk.score < random.score);
+        assert!(
+            Strength::estimate("admin", "admin_Pa!1").entropy
+                < Strength::estimate("login", "admin_Pa!1").entropy
+        );
+    }
+
+    #[test]
+    fn score_grows_with_length() {
+        let scores: Vec<u8> = [
+            "",
+            "k",
+            "x7#kP",
+            "x7#kP!q",
+            "x7#kP!q2mQ",
+            "x7#kP!q2mQ;Lr8wE",
+        ]
+        .iter()
+        .map(|password| Strength::estimate("login", password).score)
+        .collect();
+        assert_eq!(scores, vec![0, 0, 1, 2, 3, 4]);
+        assert_eq!(Strength::estimate("login", "").entropy, 0.0);
+    }
+
+    #[test]
+    fn long_password_is_estimated_quickly() {
+        let mut state = 1u64;
+        let noise: String = (0..4000)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                char::from(b'!' + (state >> 33) as u8 % 94)
+            })
+            .collect();
+        let started = Instant::now();
+        Strength::estimate("login", &noise);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let repeat = "xY7#".repeat(1000);
+        assert_eq!(
+            patterns("login", &repeat),
+            vec![(PatternKind::Repeat, repeat.to_lowercase())]
+        );
+        let sequence: String = (0x4e00..0x4e00 + 4000).filter_map(char::from_u32).collect();
+        assert_eq!(
+            patterns("login", &sequence),
+            vec![(PatternKind::Sequence, sequence.clone())]
+        );
+    }
+}
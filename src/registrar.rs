@@ -1,167 +1,244 @@
-use std::fs::OpenOptions;
-use std::io::Read;
-
-use super::utils::{check_regex, read_stdin};
-
-#[allow(non_camel_case_types)]
-type MD5_String = String;
-type CredentialsMap = std::collections::BTreeMap<MD5_String, (MD5_String, MD5_String)>;
+use super::cli::{RegisterArgs, Secret};
+use super::config::Config;
+use super::credentials::{CredentialRecord, CredentialsManager};
+use super::crypto::PasswordHash;
+use super::error::{Error, Result};
+use super::policy::{PasswordPolicy, Strength};
+use super::utils::read_stdin;
 
 pub(super) struct Registrar;
 
 impl Registrar {
+    const ADMIN_LEVEL: &'static str = "admin";
+    const USER_LEVEL: &'static str = "user";
     const LOGIN_MESSAGE: &'static str = "Введите логин:";
     const PASSWORD_MESSAGE: &'static str = "Введите пароль:";
-    const ACCESS_LEVEL_MESSAGE: &'static str = "Введите Уровень доступа:";
-    const CREDENTIALS_PATH: &'static str = "credentials.txt";
+    const PASSWORD_CONFIRMATION_MESSAGE: &'static str = "Повторите пароль:";
+    const PASSWORD_POLICY_MESSAGE: &'static str = "Пароль не соответствует требованиям:";
+    const STRENGTH_MESSAGE: &'static str = "Надёжность пароля:";
+    const PATTERNS_MESSAGE: &'static str = "Предсказуемые части пароля:";
+
+    /// Creates the credentials store on first run and registers the first user in it.
+    /// Refuses to run, if there are users already
+    pub(super) fn init(config: &Config, args: &RegisterArgs) -> Result<()> {
+        // Nothing is asked in vain. The store is checked again under the lock, when
+        // the user is added, as another process may initialize it meanwhile
+        Self::check_empty(&CredentialsManager::open(config)?)?;
+        Self::add_user(config, args, true)
+    }
+
+    /// Registers a user. Values, missing in `args`, are asked interactively.
+    /// Administrators are not registered this way: the first one is added by `init`,
+    /// others are promoted by an administrator
+    pub(super) fn register(config: &Config, args: &RegisterArgs) -> Result<()> {
+        Self::add_user(config, args, false)
+    }
 
-    pub(super) fn register() -> Result<(), String> {
+    /// Adds a user, only to an empty store if it is the `first` one. Access level,
+    /// missing in `args`, is administrator for the first user and user for others
+    fn add_user(config: &Config, args: &RegisterArgs, first: bool) -> Result<()> {
+        let access_level = match &args.access_level {
+            Some(access_level) => access_level.as_str(),
+            None if first => Self::ADMIN_LEVEL,
+            None => Self::USER_LEVEL,
+        };
+        Self::check_access_level(access_level, first)?;
         loop {
-            let login = Self::read_registration_data(
-                Self::LOGIN_MESSAGE,
-                "Failed reading user input login",
-            )?;
-            let password = Self::read_registration_data(
+            let login = match &args.login {
+                Some(login) => login.clone(),
+                None => Self::read_registration_data(
+                    Self::LOGIN_MESSAGE,
+                    "Failed reading user input login",
+                )?,
+            };
+            let password = Self::read_new_password(
+                &config.password_policy,
+                &login,
+                &args.password,
                 Self::PASSWORD_MESSAGE,
-                "Failed reading user input password",
             )?;
-            {
-                // Check password
-                if login == password {
-                    return Err("Login is equal to password".to_string());
-                }
-                Self::check_password(&password)?
-            };
-            let access_level = {
-                let raw = Self::read_registration_data(
-                    Self::ACCESS_LEVEL_MESSAGE,
-                    "Failed reading user access level",
-                )?;
-                Self::convert_to_access_lvl(&raw)?
-            };
 
-            // TODO
-            // 1. Create a different abstraction (for example, CredentialsManager),
-            // which manages (reads/writes) credentials in a separate module.
-            // 2. Make sure there are no copy paste and there are no abstractions leak
-            let mut credentials = {
-                let mut contents = String::new();
-                let mut f = OpenOptions::new()
-                    .read(true)
-                    .open(Self::CREDENTIALS_PATH)
-                    .map_err(|e| format!("Failed opening file {}: {}", Self::CREDENTIALS_PATH, e))?;
-                f.read_to_string(&mut contents)
-                    .map_err(|e| format!("Failed to read from file: {:?}", e))?;
-
-                if contents.len() == 0 {
-                    CredentialsMap::new()
-                } else {
-                    serde_json::de::from_str(&contents)
-                        .map_err(|e| format!("Failed deserializing credentials data: {:?}", e))?
+            let mut credentials = CredentialsManager::open(config)?;
+            if first {
+                Self::check_empty(&credentials)?;
+            }
+            let password_hash = PasswordHash::new(&password, config.hash_iterations)?;
+            if !credentials.insert(
+                &login,
+                CredentialRecord::new(password_hash, access_level.to_string()),
+            ) {
+                if args.login.is_some() {
+                    return Err(Error::Validation("Such user exists".to_string()));
                 }
-            };
-            if credentials
-                .insert(super::md5_utf8(&login), (super::md5_utf8(&password), access_level))
-                .is_some()
-            {
                 // If user exists, start again
                 println!("Such user exists. Performing registration again");
             } else {
-                let f = OpenOptions::new()
-                    .write(true)
-                    .truncate(true)
-                    .open(Self::CREDENTIALS_PATH)
-                    .map_err(|e| format!("Failed opening file {}: {}", Self::CREDENTIALS_PATH, e))?;
-                serde_json::ser::to_writer(f, &credentials)
-                    .map_err(|e| format!("Failed serializing credentials data: {:?}", e))?;
+                credentials.save()?;
                 break;
             }
         }
         Ok(())
     }
 
-    fn read_registration_data(prompt_msg: &str, app_err: &str) -> Result<String, String> {
-        println!("{}\t", prompt_msg);
-        read_stdin().map_err(|native_err| format!("{}: {}", app_err, native_err))
+    /// Reads a new password of the user and checks it against the policy.
+    /// Typed password is asked twice, as it can be mistyped
+    pub(super) fn read_new_password(
+        policy: &PasswordPolicy,
+        login: &str,
+        secret: &Secret,
+        prompt_msg: &str,
+    ) -> Result<String> {
+        let password = secret.read(prompt_msg, "Failed reading user input password")?;
+        Self::check_password(policy, login, &password)?;
+        if *secret == Secret::Prompt {
+            let confirmation = secret.read(
+                Self::PASSWORD_CONFIRMATION_MESSAGE,
+                "Failed reading user input password confirmation",
+            )?;
+            Self::check_confirmation(&password, &confirmation)?;
+        }
+        Ok(password)
     }
 
-    fn check_password(password: &str) -> Result<(), String> {
-        Self::check_length(password)?;
-        Self::check_symbols(password)
+    /// Only the first user may register as an administrator
+    fn check_access_level(access_level: &str, first: bool) -> Result<()> {
+        if !first && access_level == Self::ADMIN_LEVEL {
+            return Err(Error::Policy(
+                "Administrators can not register themselves. Ask an administrator \
+                 to grant the access level"
+                    .to_string(),
+            ));
+        }
+        Ok(())
     }
 
-    fn check_length(password: &str) -> Result<(), String> {
-        if password.len() <= 7 {
-            return Err("Password length is less than 8 symbols".to_string());
+    fn check_empty(credentials: &CredentialsManager) -> Result<()> {
+        if !credentials.is_empty() {
+            return Err(Error::Policy(
+                "Credentials store is initialized already".to_string(),
+            ));
         }
         Ok(())
     }
 
-    fn check_symbols(password: &str) -> Result<(), String> {
-        // Check upper case letters
-        check_regex(
-            r"[A-Z]",
-            password,
-            "Check password has upper case letters failed",
-        )?;
-        // Check upper case letters
-        check_regex(
-            r"[a-z]",
-            password,
-            "Check password has lower case letters failed",
-        )?;
-        // Check numbers
-        check_regex(r"[0-9]", password, "Check password has numbers failed")?;
-        // Check has special symbols
-        let re = format!("[{}]", regex::escape("!№@#$%^&*():;[]?*()-_=+{},.\""));
-        check_regex(&re, password, "Check password has special symbols failed")?;
-        // Check has no spaces
-        check_regex(r"^\S*$", password, "Check password has no spaces failed")
+    fn read_registration_data(prompt_msg: &str, app_err: &str) -> Result<String> {
+        println!("{}\t", prompt_msg);
+        read_stdin().map_err(|native_err| Error::io(app_err, native_err))
     }
 
-    fn convert_to_access_lvl(lvl: &str) -> Result<String, String> {
-        match lvl {
-            "1" => Ok("admin".to_string()),
-            "2" => Ok("user".to_string()),
-            _ => Err("There is no such access level".to_string()),
+    fn check_confirmation(password: &str, confirmation: &str) -> Result<()> {
+        if password != confirmation {
+            return Err(Error::Validation("Passwords do not match".to_string()));
         }
+        Ok(())
+    }
+
+    /// Shows estimated strength of the password and checks it against the policy.
+    /// If any rule fails, shows the checklist of all rules
+    fn check_password(policy: &PasswordPolicy, login: &str, password: &str) -> Result<()> {
+        let strength = Strength::estimate(login, password);
+        println!(
+            "{} {}/{} ({:.0} бит)",
+            Self::STRENGTH_MESSAGE,
+            strength.score,
+            Strength::MAX_SCORE,
+            strength.entropy
+        );
+        if !strength.patterns.is_empty() {
+            println!("{}", Self::PATTERNS_MESSAGE);
+            for pattern in &strength.patterns {
+                println!("  - {}", pattern.description());
+            }
+        }
+
+        let violations = policy.violations(login, password);
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        println!("{}", Self::PASSWORD_POLICY_MESSAGE);
+        for (rule, satisfied) in policy.checklist(login, password) {
+            let mark = if satisfied { '+' } else { '-' };
+            println!("  [{}] {}", mark, rule.description());
+        }
+        Err(Error::WeakPassword(violations))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Registrar;
+    use std::fs;
+    use std::path::PathBuf;
 
-    // TODO
-    // 1. Write tests, to check all cases in "check_symbols"
-    // 2. Write test for checking passwords using each of special_symbol
-    // "@", r#"""#, "#", "№", r#"$"#, ";", "%", "^", ":", ";", "&", "?", "*", "(", ")", "_",
-    // "-", "=", "+", "{", "}", ".", ",", "!",
+    use super::super::credentials::testing::{test_config, TempPath};
+    use super::super::credentials::StorageKind;
+    use super::super::error::Error;
+    use super::{CredentialsManager, RegisterArgs, Registrar, Secret};
 
     #[test]
-    fn check_password_length() {
-        let password = "some_pa\n";
-        assert_eq!(password.len(), 8);
-        assert!(Registrar::check_length(password).is_ok());
-        assert!(Registrar::check_length(password.trim()).is_err())
+    fn init_adds_only_first_user() {
+        let path = TempPath::new("registrar-init");
+        let password = TempPath::new("registrar-init-password");
+        fs::write(&*password, "somePa@_ss1\n").unwrap();
+        let config = test_config(StorageKind::Json, &path);
+        let args = |login: &str| RegisterArgs {
+            login: Some(login.to_string()),
+            access_level: Some("admin".to_string()),
+            password: Secret::File(PathBuf::from(&*password)),
+        };
+
+        Registrar::init(&config, &args("root")).unwrap();
+        assert!(matches!(
+            Registrar::init(&config, &args("other_root")),
+            Err(Error::Policy(_))
+        ));
+        // Parallel init, which found the store empty before the first user was added
+        assert!(matches!(
+            Registrar::add_user(&config, &args("other_root"), true),
+            Err(Error::Policy(_))
+        ));
+        assert_eq!(
+            CredentialsManager::open(&config).unwrap().records().count(),
+            1
+        );
     }
 
     #[test]
-    fn check_password_is_valid() {
-        let password_valid = "somePa@_ss1";
-        let invalid_passwords = [
-            "somepass",
-            "SOMEPASS",
-            "Somepass",
-            "somePass1",
-            "somePa@ ss1",
-        ];
-
-        assert!(Registrar::check_symbols(password_valid).is_ok());
-
-        for invalid_pass in invalid_passwords {
-            assert!(Registrar::check_symbols(invalid_pass).is_err());
-        }
+    fn self_registration_as_admin_is_refused() {
+        let path = TempPath::new("registrar-register");
+        let password = TempPath::new("registrar-register-password");
+        fs::write(&*password, "somePa@_ss1\n").unwrap();
+        let config = test_config(StorageKind::Json, &path);
+        let args = |login: &str, access_level: &str| RegisterArgs {
+            login: Some(login.to_string()),
+            access_level: Some(access_level.to_string()),
+            password: Secret::File(PathBuf::from(&*password)),
+        };
+
+        assert!(matches!(
+            Registrar::register(&config, &args("eve", "admin")),
+            Err(Error::Policy(_))
+        ));
+        assert!(CredentialsManager::open(&config).unwrap().is_empty());
+        // Level is not asked, users register as users
+        let args = RegisterArgs {
+            access_level: None,
+            ..args("bob", "user")
+        };
+        Registrar::register(&config, &args).unwrap();
+        assert_eq!(
+            CredentialsManager::open(&config)
+                .unwrap()
+                .get("bob")
+                .map(|record| record.access_level.as_str()),
+            Some("user")
+        );
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn check_password_confirmation() {
+        assert!(Registrar::check_confirmation("somePa@_ss1", "somePa@_ss1").is_ok());
+        assert!(Registrar::check_confirmation("somePa@_ss1", "somePa@_ss2").is_err());
+        assert!(Registrar::check_confirmation("somePa@_ss1", "").is_err());
+    }
+}
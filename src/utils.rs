@@ -1,17 +1,30 @@
-use std::io;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
 
-use regex::Regex;
-
-pub(super) fn read_stdin() -> Result<String, String> {
+pub(super) fn read_stdin() -> io::Result<String> {
     let mut ret = String::new();
-    io::stdin().read_line(&mut ret).map_err(|e| e.to_string())?;
+    io::stdin().read_line(&mut ret)?;
     Ok(ret.trim().to_string())
 }
 
-pub(super) fn check_regex(re: &str, text: &str, err_message: &str) -> Result<(), String> {
-    Regex::new(re)
-        .map_err(|e| e.to_string())?
-        .find(text)
-        .ok_or(err_message.to_string())
-        .map(|_| ())
-}
\ No newline at end of file
+/// Reads a line without echoing it, if stdin is a terminal. Otherwise reads stdin as is,
+/// so the input may be piped
+pub(super) fn read_secret() -> io::Result<String> {
+    if io::stdin().is_terminal() {
+        rpassword::read_password().map(|secret| secret.trim().to_string())
+    } else {
+        read_stdin()
+    }
+}
+
+/// Reads the first line of a file, e.g. of `/dev/fd/N`
+pub(super) fn read_secret_file(path: &Path) -> io::Result<String> {
+    let contents = fs::read_to_string(path)?;
+    Ok(contents
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_string())
+}